@@ -0,0 +1,428 @@
+use crate::bytecode::Opcodes;
+use crate::chunk::Chunk;
+use crate::lox_string_table::LoxStringTable;
+use crate::scanner::SourcePosition;
+use crate::value::Value;
+use std::fmt;
+
+// Turns the disassembler's output back into a chunk, so bytecode can be
+// written or edited by hand and run. Each line is one instruction:
+//
+//   0000    1 OP_CONSTANT      0001 '1.5'
+//   0002    | OP_DEFINE_GLOBAL 0000 'a'
+//
+// The offset is ignored, so instructions can be added and removed without
+// renumbering. The line number may be left out (or be `|`) to keep the
+// previous one, and the constant index may be left out to reuse an equal
+// constant or add a new one. Blank lines, `== name ==` headers and lines
+// starting with ';' are skipped.
+//
+// Only line numbers are kept from the source positions, so errors from an
+// assembled chunk can't point at columns.
+//
+// TODO - there are no jumps yet. When there are, accept `label:` lines and
+// label names as jump operands, patching in the offsets once every
+// instruction has been read.
+
+#[derive(Debug, PartialEq)]
+pub struct AssembleError {
+    pub line: usize, // Line of the assembly text, not the original source.
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+struct Assembler<'a> {
+    chunk: Chunk,
+    // Constants are placed at the index they're given, so slots can be filled
+    // out of order. Unused slots end up nil.
+    constants: Vec<Option<Value>>,
+    string_table: &'a mut LoxStringTable,
+    source_line: i32,
+}
+
+// Numbers are compared by their bits, so a NaN constant matches itself.
+fn same_constant(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::ValNumber(a), Value::ValNumber(b)) => a.to_bits() == b.to_bits(),
+        _ => a == b,
+    }
+}
+
+// Undoes the escaping of `{:?}` on a str, which is how the disassembler shows
+// string constants. `text` includes the surrounding quotes.
+fn unescape(text: &str) -> Result<String, String> {
+    let inner = match text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+    {
+        Some(inner) => inner,
+        None => return Err(format!("Unterminated string {}.", text)),
+    };
+
+    let mut string = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => string.push('\n'),
+            Some('r') => string.push('\r'),
+            Some('t') => string.push('\t'),
+            Some('0') => string.push('\0'),
+            Some('\\') => string.push('\\'),
+            Some('"') => string.push('"'),
+            Some('\'') => string.push('\''),
+            Some('u') => {
+                let rest = chars.as_str();
+                let escaped = rest
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .and_then(|(hex, _)| u32::from_str_radix(hex, 16).ok().map(|c| (hex, c)))
+                    .and_then(|(hex, c)| std::char::from_u32(c).map(|c| (hex, c)));
+
+                match escaped {
+                    Some((hex, c)) => {
+                        string.push(c);
+                        chars = rest[hex.len() + 2..].chars();
+                    }
+                    None => return Err(String::from("Bad unicode escape.")),
+                }
+            }
+            _ => return Err(format!("Bad escape in string {}.", text)),
+        }
+    }
+
+    Ok(string)
+}
+
+impl<'a> Assembler<'a> {
+    fn parse_constant(&mut self, opcode: Opcodes, text: &str) -> Result<Value, String> {
+        if text.starts_with('"') {
+            let string = unescape(text)?;
+            let string = self.string_table.allocate_string_from_str(&string);
+            return string
+                .map(Value::ValObjString)
+                .map_err(|error| error.to_string());
+        }
+
+        if opcode != Opcodes::OpConstant {
+            let string = self.string_table.allocate_string_from_str(text);
+            return string
+                .map(Value::ValObjString)
+                .map_err(|error| error.to_string());
+        }
+
+        match text {
+            "nil" => Ok(Value::ValNil),
+            "true" => Ok(Value::ValBool(true)),
+            "false" => Ok(Value::ValBool(false)),
+            _ => match text.parse::<f64>() {
+                Ok(number) => Ok(Value::ValNumber(number)),
+                Err(_) => Err(format!(
+                    "Can't read constant '{}', strings need double quotes.",
+                    text
+                )),
+            },
+        }
+    }
+
+    // Finds the index for a constant, adding it if needed.
+    fn constant_index(&mut self, index: Option<usize>, value: Value) -> Result<u8, String> {
+        let index = match index {
+            Some(index) => index,
+            None => self
+                .constants
+                .iter()
+                .position(|existing| match existing {
+                    Some(existing) => same_constant(existing, &value),
+                    None => false,
+                })
+                .unwrap_or(self.constants.len()),
+        };
+
+        if index > u8::MAX as usize {
+            return Err(format!("Constant index {} is too large.", index));
+        }
+
+        if index >= self.constants.len() {
+            self.constants.resize(index + 1, None);
+        }
+
+        match &self.constants[index] {
+            Some(existing) if !same_constant(existing, &value) => {
+                return Err(format!(
+                    "Constant {} is both '{}' and '{}'.",
+                    index, existing, value
+                ));
+            }
+            _ => self.constants[index] = Some(value),
+        }
+
+        Ok(index as u8)
+    }
+
+    // Reads the operand of an instruction that takes a constant, returning the
+    // byte to write, if any.
+    fn operand(&mut self, opcode: Opcodes, text: &str) -> Result<Option<u8>, String> {
+        if text == "<missing operand>" {
+            return Ok(None);
+        }
+
+        let (index_text, rest) = match text.find(|c: char| c.is_whitespace() || c == '\'') {
+            Some(end) => (&text[..end], text[end..].trim_start()),
+            None => (text, ""),
+        };
+        let index = if index_text.is_empty() {
+            None
+        } else {
+            match index_text.parse::<usize>() {
+                Ok(index) => Some(index),
+                Err(_) => return Err(format!("Expected a constant index, got '{}'.", index_text)),
+            }
+        };
+
+        if rest == "<bad constant>" {
+            return match index {
+                Some(index) if index <= u8::MAX as usize => Ok(Some(index as u8)),
+                _ => Err(String::from("A bad constant needs a valid index.")),
+            };
+        }
+
+        // The value runs between the first and last quote, as string constants
+        // may contain quotes themselves.
+        if rest.len() < 2 || !rest.starts_with('\'') || !rest.ends_with('\'') {
+            return Err(format!(
+                "Expected a quoted constant after {}.",
+                opcode.name()
+            ));
+        }
+
+        let value = self.parse_constant(opcode, &rest[1..rest.len() - 1])?;
+        Ok(Some(self.constant_index(index, value)?))
+    }
+
+    fn instruction(&mut self, line: &str) -> Result<(), String> {
+        let mut words = line.split_whitespace();
+        let mut prefix = Vec::new();
+
+        // Everything before the mnemonic is the offset and line number.
+        let mnemonic = loop {
+            match words.next() {
+                Some(word) if word.starts_with("OP_") || word == "Unknown" => break word,
+                Some(word) => prefix.push(word),
+                None => return Err(format!("Expected an instruction, got '{}'.", line)),
+            }
+        };
+
+        let source_line = match prefix.as_slice() {
+            [] | ["|"] | [_, "|"] => None,
+            [line] | [_, line] => Some(line),
+            _ => {
+                return Err(format!(
+                    "Unexpected '{}' before the instruction.",
+                    prefix[2]
+                ))
+            }
+        };
+        if let Some(source_line) = source_line {
+            match source_line.parse() {
+                Ok(source_line) => self.source_line = source_line,
+                Err(_) => return Err(format!("Expected a line number, got '{}'.", source_line)),
+            }
+        }
+
+        let position = SourcePosition {
+            line: self.source_line,
+            column: 0,
+            start: 0,
+            end: 0,
+        };
+        let rest = line[line.find(mnemonic).unwrap() + mnemonic.len()..].trim();
+
+        if mnemonic == "Unknown" {
+            let byte = rest
+                .strip_prefix("opcode")
+                .and_then(|byte| byte.trim().parse::<u8>().ok());
+
+            return match byte {
+                Some(byte) => {
+                    self.chunk.write_chunk(byte, position);
+                    Ok(())
+                }
+                None => Err(format!("Expected 'Unknown opcode N', got '{}'.", line)),
+            };
+        }
+
+        let opcode = match Opcodes::from_name(mnemonic) {
+            Some(opcode) => opcode,
+            None => return Err(format!("Unknown instruction {}.", mnemonic)),
+        };
+
+        let takes_constant = matches!(
+            opcode,
+            Opcodes::OpConstant
+                | Opcodes::OpGetGlobal
+                | Opcodes::OpDefineGlobal
+                | Opcodes::OpSetGlobal
+        );
+
+        let operand = if takes_constant {
+            self.operand(opcode, rest)?
+        } else if !rest.is_empty() {
+            return Err(format!("{} doesn't take an operand.", opcode.name()));
+        } else {
+            None
+        };
+
+        self.chunk.write_chunk(opcode as u8, position);
+        if let Some(operand) = operand {
+            self.chunk.write_chunk(operand, position);
+        }
+
+        Ok(())
+    }
+}
+
+/// Assembles text in the disassembler's format into a chunk, interning its
+/// strings into `string_table`. The chunk isn't verified, so bytecode that
+/// would crash the VM can be written on purpose.
+pub fn assemble(text: &str, string_table: &mut LoxStringTable) -> Result<Chunk, AssembleError> {
+    let mut assembler = Assembler {
+        chunk: Chunk::new(),
+        constants: Vec::new(),
+        string_table,
+        source_line: 1,
+    };
+
+    for (index, line) in text.lines().enumerate() {
+        let line_text = line.trim();
+
+        if line_text.is_empty() || line_text.starts_with(';') || line_text.starts_with("==") {
+            continue;
+        }
+
+        if let Err(message) = assembler.instruction(line_text) {
+            return Err(AssembleError {
+                line: index + 1,
+                message,
+            });
+        }
+    }
+
+    let mut chunk = assembler.chunk;
+    chunk.constants = assembler
+        .constants
+        .into_iter()
+        .map(|constant| constant.unwrap_or(Value::ValNil))
+        .collect();
+
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+    use crate::compiler;
+    use crate::lox_string_table::LoxStringTable;
+    use crate::value::Value;
+
+    #[test]
+    fn round_trip_test() {
+        let mut table = LoxStringTable::new();
+        let source = String::from(
+            "var a = \"it's\";\nvar b = \"1\";\nprint a + b;\na = nil;\nprint !true == false;\nprint -(1.5 / 3);",
+        );
+        let chunk = compiler::compile(&mut table, &source, 1).ok().unwrap();
+        let text = chunk.disassemble_to_string("code");
+
+        let assembled = assemble(&text, &mut table).unwrap();
+
+        assert_eq!(assembled.code, chunk.code);
+        assert_eq!(assembled.constants, chunk.constants);
+        assert_eq!(assembled.disassemble_to_string("code"), text);
+    }
+
+    #[test]
+    fn hand_written_test() {
+        let mut table = LoxStringTable::new();
+        let text = "; Prints 3.\n\
+                    OP_CONSTANT '1'\n\
+                    2 OP_CONSTANT '2'\n\
+                    OP_ADD\n\
+                    OP_CONSTANT 0 '1'\n\
+                    OP_DEFINE_GLOBAL 'x'\n\
+                    OP_CONSTANT '\"multi\\nline\"'\n\
+                    OP_POP\n\
+                    Unknown opcode 250\n\
+                    OP_GET_GLOBAL 9 <bad constant>\n\
+                    OP_RETURN";
+
+        let chunk = assemble(text, &mut table).unwrap();
+
+        assert_eq!(
+            chunk.disassemble_to_string("code"),
+            "== code ==\n\
+             0000    1 OP_CONSTANT      0000 '1'\n\
+             0002    2 OP_CONSTANT      0001 '2'\n\
+             0004    | OP_ADD\n\
+             0005    | OP_CONSTANT      0000 '1'\n\
+             0007    | OP_DEFINE_GLOBAL 0002 'x'\n\
+             0009    | OP_CONSTANT      0003 '\"multi\\nline\"'\n\
+             0011    | OP_POP\n\
+             0012    | Unknown opcode 250\n\
+             0013    | OP_GET_GLOBAL    0009 <bad constant>\n\
+             0015    | OP_RETURN\n"
+        );
+        assert_eq!(chunk.constants.len(), 4);
+        assert_eq!(
+            chunk.constants[3],
+            Value::ValObjString(table.allocate_string_from_str("multi\nline").unwrap())
+        );
+    }
+
+    fn assemble_error(text: &str) -> String {
+        let mut table = LoxStringTable::new();
+        assemble(text, &mut table).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn assemble_errors_test() {
+        assert_eq!(
+            assemble_error("OP_NIL\nOP_FOO"),
+            "line 2: Unknown instruction OP_FOO."
+        );
+        assert_eq!(
+            assemble_error("OP_NIL 3"),
+            "line 1: OP_NIL doesn't take an operand."
+        );
+        assert_eq!(
+            assemble_error("OP_CONSTANT 0 'abc'"),
+            "line 1: Can't read constant 'abc', strings need double quotes."
+        );
+        assert_eq!(
+            assemble_error("OP_CONSTANT 0 '1'\nOP_CONSTANT 0 '2'"),
+            "line 2: Constant 0 is both '1' and '2'."
+        );
+        assert_eq!(
+            assemble_error("OP_CONSTANT 0"),
+            "line 1: Expected a quoted constant after OP_CONSTANT."
+        );
+        assert_eq!(
+            assemble_error("0000 1 2 OP_NIL"),
+            "line 1: Unexpected '2' before the instruction."
+        );
+        assert_eq!(
+            assemble_error("nil"),
+            "line 1: Expected an instruction, got 'nil'."
+        );
+    }
+}
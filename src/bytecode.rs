@@ -1,4 +1,4 @@
-#[derive(FromPrimitive, ToPrimitive)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, FromPrimitive, ToPrimitive)]
 #[repr(u8)]
 pub enum Opcodes {
     OpReturn,
@@ -21,3 +21,41 @@ pub enum Opcodes {
     OpDivide,
     OpNot,
 }
+
+impl Opcodes {
+    /// The name used by the disassembler and assembler.
+    pub fn name(self) -> &'static str {
+        match self {
+            Opcodes::OpReturn => "OP_RETURN",
+            Opcodes::OpPrint => "OP_PRINT",
+            Opcodes::OpConstant => "OP_CONSTANT",
+            Opcodes::OpNil => "OP_NIL",
+            Opcodes::OpTrue => "OP_TRUE",
+            Opcodes::OpFalse => "OP_FALSE",
+            Opcodes::OpPop => "OP_POP",
+            Opcodes::OpGetGlobal => "OP_GET_GLOBAL",
+            Opcodes::OpDefineGlobal => "OP_DEFINE_GLOBAL",
+            Opcodes::OpSetGlobal => "OP_SET_GLOBAL",
+            Opcodes::OpEqual => "OP_EQUAL",
+            Opcodes::OpGreater => "OP_GREATER",
+            Opcodes::OpLess => "OP_LESS",
+            Opcodes::OpNegate => "OP_NEGATE",
+            Opcodes::OpAdd => "OP_ADD",
+            Opcodes::OpSubtract => "OP_SUBTRACT",
+            Opcodes::OpMultiply => "OP_MULTIPLY",
+            Opcodes::OpDivide => "OP_DIVIDE",
+            Opcodes::OpNot => "OP_NOT",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Opcodes> {
+        (0..=u8::MAX)
+            .filter_map(num::FromPrimitive::from_u8)
+            .find(|opcode: &Opcodes| opcode.name() == name)
+    }
+}
+
+/// Version of the opcode numbering above, saved in compiled `.loxc` files.
+/// Bump it whenever opcodes are added, removed or reordered, so files compiled
+/// against a different set are rejected instead of misread.
+pub const OPCODE_VERSION: u16 = 1;
@@ -1,21 +1,24 @@
 use crate::bytecode::Opcodes;
+use crate::scanner::SourcePosition;
 use crate::value::Value;
+use std::fmt;
+use std::io;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Chunk {
     pub code: Vec<u8>,
-    pub lines: Vec<i32>,
+    pub positions: Vec<SourcePosition>,
     pub constants: Vec<Value>,
 }
 
 impl Chunk {
-    pub fn write_chunk(&mut self, byte: u8, line: i32) {
+    pub fn write_chunk(&mut self, byte: u8, position: SourcePosition) {
         self.code.push(byte);
-        self.lines.push(line);
+        self.positions.push(position);
     }
 
-    // pub fn add_instruction(&mut self, instruction: Opcodes, line: i32) {
-    //     self.write_chunk(instruction as u8, line);
+    // pub fn add_instruction(&mut self, instruction: Opcodes, position: SourcePosition) {
+    //     self.write_chunk(instruction as u8, position);
     // }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
@@ -23,90 +26,224 @@ impl Chunk {
         self.constants.len() - 1
     }
 
-    pub fn reset(&mut self) {
-        self.code.clear();
-        self.lines.clear();
-        self.constants.clear();
+    pub fn new() -> Self {
+        Chunk {
+            code: Vec::new(),
+            positions: Vec::new(),
+            constants: Vec::new(),
+        }
     }
 
-    pub fn dissasemble(&self, name: &str) {
-        println!("== {0} ==", name);
+    /// Disassembles the whole chunk, headed by `name`.
+    pub fn disassemble<W: fmt::Write + ?Sized>(&self, out: &mut W, name: &str) -> fmt::Result {
+        writeln!(out, "== {0} ==", name)?;
         let mut offset = 0;
 
         while offset < self.code.len() {
-            offset += self.dissasemble_instruction(offset) as usize;
+            offset += self.disassemble_instruction(out, offset)?;
         }
+
+        Ok(())
     }
 
-    pub fn new() -> Self {
-        let chunk = Chunk {
-            code: Vec::new(),
-            lines: Vec::new(),
-            constants: Vec::new(),
+    pub fn disassemble_to_string(&self, name: &str) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail.
+        let _ = self.disassemble(&mut out, name);
+        out
+    }
+
+    pub fn disassemble_to_io(&self, out: &mut dyn io::Write, name: &str) -> io::Result<()> {
+        let mut adapter = IoAdapter::new(out);
+        let result = self.disassemble(&mut adapter, name);
+        adapter.into_result(result)
+    }
+
+    pub fn disassemble_instruction_to_io(
+        &self,
+        out: &mut dyn io::Write,
+        offset: usize,
+    ) -> io::Result<usize> {
+        let mut adapter = IoAdapter::new(out);
+        let result = self.disassemble_instruction(&mut adapter, offset);
+        adapter.into_result(result)
+    }
+
+    /// The size of the instruction starting at the specified offset. Unknown
+    /// opcodes count as one byte, and an instruction cut short by the end of
+    /// the code only counts the bytes that are there, so stepping through a
+    /// chunk by this always ends exactly at the end of the code.
+    pub fn instruction_size(&self, offset: usize) -> usize {
+        let size = match self
+            .code
+            .get(offset)
+            .map(|byte| num::FromPrimitive::from_u8(*byte))
+        {
+            Some(Some(Opcodes::OpConstant))
+            | Some(Some(Opcodes::OpGetGlobal))
+            | Some(Some(Opcodes::OpDefineGlobal))
+            | Some(Some(Opcodes::OpSetGlobal)) => 2,
+            _ => 1,
         };
 
-        chunk
+        size.min(self.code.len().saturating_sub(offset))
     }
 
-    /// Disassembles the instruction starting at the specified offset.
+    /// Disassembles the instruction starting at the specified offset. Bad
+    /// bytecode, like an unknown opcode or a missing constant, is shown as such
+    /// instead of panicking.
     ///
     /// Returns the size of the disassembled instruction.
-    pub fn dissasemble_instruction(&self, offset: usize) -> u8 {
-        print!("{:04} ", offset);
+    pub fn disassemble_instruction<W: fmt::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        offset: usize,
+    ) -> Result<usize, fmt::Error> {
+        write!(out, "{:04} ", offset)?;
 
-        if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
-            print!("   | ");
-        } else {
-            print!("{:4} ", self.lines[offset]);
+        let line = |offset: usize| self.positions.get(offset).map(|position| position.line);
+        match line(offset) {
+            Some(current) if offset > 0 && line(offset - 1) == Some(current) => {
+                write!(out, "   | ")?
+            }
+            Some(current) => write!(out, "{:4} ", current)?,
+            None => write!(out, "   ? ")?,
         }
 
-        let instruction = num::FromPrimitive::from_u8(self.code[offset]);
-
-        match instruction {
-            Some(Opcodes::OpReturn) => Chunk::simple_instruction("OP_RETURN", offset),
-            Some(Opcodes::OpConstant) => Chunk::constant_instruction("OP_CONSTANT", self, offset),
-            Some(Opcodes::OpNil) => Chunk::simple_instruction("OP_NIL", offset),
-            Some(Opcodes::OpTrue) => Chunk::simple_instruction("OP_TRUE", offset),
-            Some(Opcodes::OpFalse) => Chunk::simple_instruction("OP_FALSE", offset),
-            Some(Opcodes::OpPop) => Chunk::simple_instruction("OP_POP", offset),
-            Some(Opcodes::OpEqual) => Chunk::simple_instruction("OP_EQUAL", offset),
-            Some(Opcodes::OpGreater) => Chunk::simple_instruction("OP_GREATER", offset),
-            Some(Opcodes::OpLess) => Chunk::simple_instruction("OP_LESS", offset),
-            Some(Opcodes::OpNegate) => Chunk::simple_instruction("OP_NEGATE", offset),
-            Some(Opcodes::OpAdd) => Chunk::simple_instruction("OP_ADD", offset),
-            Some(Opcodes::OpSubtract) => Chunk::simple_instruction("OP_SUBTRACT", offset),
-            Some(Opcodes::OpMultiply) => Chunk::simple_instruction("OP_MULTIPLY", offset),
-            Some(Opcodes::OpDivide) => Chunk::simple_instruction("OP_DIVIDE", offset),
-            Some(Opcodes::OpNot) => Chunk::simple_instruction("OP_NOT", offset),
-            Some(Opcodes::OpPrint) => Chunk::simple_instruction("OP_PRINT", offset),
-            Some(Opcodes::OpGetGlobal) => {
-                Chunk::constant_instruction("OP_GET_GLOBAL", self, offset)
+        match num::FromPrimitive::from_u8(self.code[offset]) {
+            Some(opcode @ Opcodes::OpConstant)
+            | Some(opcode @ Opcodes::OpGetGlobal)
+            | Some(opcode @ Opcodes::OpDefineGlobal)
+            | Some(opcode @ Opcodes::OpSetGlobal) => {
+                self.constant_instruction(out, opcode, offset)?
             }
-            Some(Opcodes::OpDefineGlobal) => {
-                Chunk::constant_instruction("OP_DEFINE_GLOBAL", self, offset)
-            }
-            Some(Opcodes::OpSetGlobal) => {
-                Chunk::constant_instruction("OP_SET_GLOBAL", self, offset)
-            }
-            // Some(_) => unimplemented!("Opcode not implemented {}", self.code[offset]),
-            None => {
-                print!("Unknown opcode {0}\n", self.code[offset]);
-                1
+            Some(opcode) => Chunk::simple_instruction(out, opcode)?,
+            None => writeln!(out, "Unknown opcode {0}", self.code[offset])?,
+        }
+
+        Ok(self.instruction_size(offset))
+    }
+
+    fn simple_instruction<W: fmt::Write + ?Sized>(out: &mut W, opcode: Opcodes) -> fmt::Result {
+        writeln!(out, "{}", opcode.name())
+    }
+
+    // Constants loaded with OP_CONSTANT show strings quoted and escaped, so
+    // they can't be mistaken for other values (like the string "1" for the
+    // number) and the output can be assembled back into the same chunk.
+    // Global names are always strings, so they're shown as they are.
+    fn constant_instruction<W: fmt::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        opcode: Opcodes,
+        offset: usize,
+    ) -> fmt::Result {
+        let name = opcode.name();
+        let constant = match self.code.get(offset + 1) {
+            Some(constant) => *constant,
+            None => return writeln!(out, "{:16} <missing operand>", name),
+        };
+
+        match self.constants.get(constant as usize) {
+            Some(Value::ValObjString(value)) if opcode == Opcodes::OpConstant => {
+                writeln!(out, "{:16} {:04} '{:?}'", name, constant, value.as_str())
             }
+            Some(value) => writeln!(out, "{:16} {:04} '{}'", name, constant, value),
+            None => writeln!(out, "{:16} {:04} <bad constant>", name, constant),
+        }
+    }
+}
+
+// Lets the disassembler, which writes to a `fmt::Write`, write to an
+// `io::Write` instead, keeping hold of the underlying error.
+struct IoAdapter<'a> {
+    out: &'a mut dyn io::Write,
+    error: Option<io::Error>,
+}
+
+impl<'a> IoAdapter<'a> {
+    fn new(out: &'a mut dyn io::Write) -> Self {
+        IoAdapter { out, error: None }
+    }
+
+    fn into_result<T>(self, result: Result<T, fmt::Error>) -> io::Result<T> {
+        match (result, self.error) {
+            (Ok(value), _) => Ok(value),
+            (Err(_), Some(error)) => Err(error),
+            (Err(_), None) => Err(io::Error::other("formatting failed")),
         }
     }
+}
 
-    fn simple_instruction(name: &str, _offset: usize) -> u8 {
-        println!("{}", name);
-        1
+impl<'a> fmt::Write for IoAdapter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.out.write_all(s.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+            fmt::Error
+        })
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Chunk;
+    use crate::bytecode::Opcodes;
+    use crate::scanner::SourcePosition;
+    use crate::value::Value;
+
+    fn position(line: i32) -> SourcePosition {
+        SourcePosition {
+            line,
+            column: 1,
+            start: 0,
+            end: 0,
+        }
+    }
+
+    #[test]
+    fn disassemble_test() {
+        let mut chunk = Chunk::new();
+        let constant = chunk.add_constant(Value::ValNumber(1.5)) as u8;
+        chunk.write_chunk(Opcodes::OpConstant as u8, position(1));
+        chunk.write_chunk(constant, position(1));
+        chunk.write_chunk(Opcodes::OpPrint as u8, position(1));
+        chunk.write_chunk(Opcodes::OpReturn as u8, position(2));
+
+        assert_eq!(
+            chunk.disassemble_to_string("test"),
+            "== test ==\n\
+             0000    1 OP_CONSTANT      0000 '1.5'\n\
+             0002    | OP_PRINT\n\
+             0003    2 OP_RETURN\n"
+        );
+
+        let mut bytes = Vec::new();
+        chunk.disassemble_to_io(&mut bytes, "test").unwrap();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            chunk.disassemble_to_string("test")
+        );
+    }
+
+    #[test]
+    fn disassemble_bad_bytecode_test() {
+        let mut chunk = Chunk::new();
+        chunk.write_chunk(200, position(1));
+        chunk.write_chunk(Opcodes::OpGetGlobal as u8, position(1));
+        chunk.write_chunk(3, position(1));
+        chunk.write_chunk(Opcodes::OpConstant as u8, position(1));
+
+        assert_eq!(chunk.instruction_size(0), 1);
+        assert_eq!(chunk.instruction_size(1), 2);
+        assert_eq!(chunk.instruction_size(3), 1);
+        assert_eq!(chunk.instruction_size(4), 0);
 
-    fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> u8 {
-        let constant = chunk.code[offset + 1];
-        print!(
-            "{:16} {:04} '{}'\n",
-            name, constant, chunk.constants[constant as usize]
+        assert_eq!(
+            chunk.disassemble_to_string("bad"),
+            "== bad ==\n\
+             0000    1 Unknown opcode 200\n\
+             0001    | OP_GET_GLOBAL    0003 <bad constant>\n\
+             0003    | OP_CONSTANT      <missing operand>\n"
         );
-        2
     }
 }
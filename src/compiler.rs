@@ -1,11 +1,14 @@
 use crate::bytecode::Opcodes;
 use crate::chunk::Chunk;
-use crate::debug::DEBUG_PRINT_CODE;
+use crate::diagnostic::Diagnostic;
+use crate::diagnostic::Severity;
 use crate::lox_string_table::LoxStringTable;
 use crate::scanner;
 use crate::scanner::Scanner;
+use crate::scanner::SourcePosition;
 use crate::scanner::Token;
 use crate::scanner::TokenType;
+use crate::suggest;
 use crate::value::Value;
 use crate::vm::InterpretError;
 
@@ -23,6 +26,9 @@ struct Parser<'a> {
     previous: Token<'a>,
     had_error: bool,
     panic_mode: bool,
+    diagnostics: Vec<Diagnostic>,
+    max_errors: usize,
+    depth: usize, // How deeply the expression being parsed is nested.
     compiling_chunk: Chunk,
     string_table: &'a mut LoxStringTable,
 }
@@ -70,28 +76,41 @@ impl Precedence {
 }
 
 impl<'a> Parser<'a> {
-    fn new(string_table: &'a mut LoxStringTable, source: &'a std::string::String) -> Self {
+    fn new(
+        string_table: &'a mut LoxStringTable,
+        source: &'a std::string::String,
+        max_errors: usize,
+    ) -> Self {
         Parser {
             scanner: scanner::Scanner::new(source),
             current: Token {
                 string: "Parser placeholder.",
                 line: -1,
+                column: -1,
+                start: 0,
+                end: 0,
                 token_type: TokenType::TokenError,
             },
             previous: Token {
                 string: "Parser placeholder.",
                 line: -1,
+                column: -1,
+                start: 0,
+                end: 0,
                 token_type: TokenType::TokenError,
             },
             had_error: false,
             panic_mode: false,
+            diagnostics: Vec::new(),
+            max_errors,
+            depth: 0,
             compiling_chunk: Chunk::new(),
-            string_table: string_table,
+            string_table,
         }
     }
 
     fn advance(&mut self) {
-        self.previous = self.current.clone();
+        self.previous = self.current;
 
         // TODO - this smells to me like scanner really should be an iterator and we range for over this.
         loop {
@@ -130,6 +149,10 @@ impl<'a> Parser<'a> {
         self.parse_precedence(Precedence::PrecAssignment);
     }
 
+    // TODO - destructuring declarations (`var [a, b] = pair;` and
+    // `var {x, y} = point;`) need list and instance values to pull apart, with
+    // a runtime error naming the line when the shape doesn't match. Only the
+    // `a, b = b, a;` form of multiple assignment exists so far.
     fn var_declaration(&mut self) {
         let global = self.parse_variable("Expect variable name.");
 
@@ -147,9 +170,29 @@ impl<'a> Parser<'a> {
     }
 
     fn expression_statement(&mut self) {
+        let first = self.current;
+        let error_count = self.diagnostics.len();
+
         self.expression();
+
+        // A statement that's a lone identifier followed by something else, like
+        // `fucn foo()` or `pritn x;`, is probably a misspelled keyword.
+        let lone_identifier =
+            first.token_type == TokenType::TokenIdentifier && self.previous.start == first.start;
+
         self.consume(TokenType::TokenSemicolon, "Expect ';' after value.");
         self.emit_byte(opcode_u8!(OpPop));
+
+        if lone_identifier && self.diagnostics.len() > error_count {
+            let keywords = scanner::KEYWORDS.iter().map(|(keyword, _)| *keyword);
+
+            if let Some(keyword) = suggest::closest(first.string, keywords) {
+                let diagnostic = &mut self.diagnostics[error_count];
+                if diagnostic.note.is_none() {
+                    diagnostic.note = Some(format!("did you mean '{}'?", keyword));
+                }
+            }
+        }
     }
 
     fn print_statement(&mut self) {
@@ -158,6 +201,9 @@ impl<'a> Parser<'a> {
         self.emit_byte(opcode_u8!(OpPrint));
     }
 
+    // TODO - there are no blocks yet. When there are, a '}' should also end
+    // synchronizing, so an error inside a block doesn't swallow the statements
+    // after it.
     fn synchronize(&mut self) {
         self.panic_mode = false;
 
@@ -184,6 +230,15 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // TODO - `fun` declarations aren't compiled yet. When they are, parameter
+    // lists should accept defaults (`b = 10`, evaluated at call time when the
+    // argument is omitted) and a trailing `...rest` collecting extra arguments,
+    // with the call path reporting "Expected 1 to 2 arguments but got 4.".
+    //
+    // TODO - neither are `class` declarations. Traits (`trait Foo { ... }`
+    // plus `class Bar with Foo, Baz { ... }`) would copy the trait methods into
+    // the class's method table when the class is defined, erroring on name
+    // conflicts unless the class overrides the method itself.
     fn declaration(&mut self) {
         if self.match_token(TokenType::TokenVar) {
             self.var_declaration();
@@ -199,14 +254,29 @@ impl<'a> Parser<'a> {
     fn statement(&mut self) {
         if self.match_token(TokenType::TokenPrint) {
             self.print_statement();
+        } else if self.check(TokenType::TokenIdentifier)
+            && self.peek_next() == TokenType::TokenComma
+        {
+            self.multiple_assignment_statement();
         } else {
             self.expression_statement();
         }
     }
 
+    // The type of the token after the current one, without consuming either.
+    fn peek_next(&self) -> TokenType {
+        self.scanner.clone().scan_token().token_type
+    }
+
     fn emit_byte(&mut self, byte: u8) {
-        let line = self.previous.line;
-        self.current_chunk().write_chunk(byte, line);
+        let position = self.previous.position();
+        self.emit_byte_at(byte, position);
+    }
+
+    // Operators are emitted after their operands, so they pass their own token's
+    // position instead of the last token consumed.
+    fn emit_byte_at(&mut self, byte: u8, position: SourcePosition) {
+        self.current_chunk().write_chunk(byte, position);
     }
 
     fn emit_bytes(&mut self, byte1: u8, byte2: u8) {
@@ -216,10 +286,6 @@ impl<'a> Parser<'a> {
 
     fn end_compiler(&mut self) {
         self.emit_return();
-
-        if DEBUG_PRINT_CODE && !self.had_error {
-            self.current_chunk().dissasemble("code");
-        }
     }
 
     fn emit_return(&mut self) {
@@ -238,6 +304,12 @@ impl<'a> Parser<'a> {
         self.error_at(self.previous, message);
     }
 
+    // Whether as many errors have been reported as asked for. A limit of 0
+    // means there isn't one.
+    fn reached_max_errors(&self) -> bool {
+        self.max_errors != 0 && self.diagnostics.len() >= self.max_errors
+    }
+
     // TODO - is it possible to borrow the token instead of copying?
     fn error_at(&mut self, token: Token, message: &str) {
         if self.panic_mode {
@@ -245,39 +317,58 @@ impl<'a> Parser<'a> {
         }
 
         self.panic_mode = true;
+        self.had_error = true;
 
-        eprint!("[line {}] Error", token.line);
+        if self.reached_max_errors() {
+            return;
+        }
 
-        match token.token_type {
-            TokenType::TokenEof => {
-                eprint!(" at end");
-            }
-            TokenType::TokenError => {}
-            _ => {
-                eprint!(" at '{}'", token.string);
+        // An unterminated string runs to the end of the file, so anything then
+        // reported at the end is a knock on effect of it rather than a mistake
+        // of its own.
+        if token.token_type == TokenType::TokenEof {
+            if let Some(last) = self.diagnostics.last() {
+                if last.token.is_none() && last.end == token.start {
+                    return;
+                }
             }
         }
 
-        eprint!(": {}\n", message);
-        self.had_error = true;
+        let token_text = match token.token_type {
+            TokenType::TokenEof => Some(String::new()),
+            TokenType::TokenError => None,
+            _ => Some(token.string.to_string()),
+        };
+
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            message: message.to_string(),
+            line: token.line,
+            column: token.column,
+            start: token.start,
+            end: token.end,
+            token: token_text,
+            note: None,
+        });
     }
 
-    fn number(&mut self, can_assign: bool) {
+    fn number(&mut self, _can_assign: bool) {
         let value = Value::ValNumber(
             f64::from_str(self.previous.string).expect("number token contained not a number"),
         );
         self.emit_constant(value);
     }
 
-    fn string(&mut self, can_assign: bool) {
+    fn string(&mut self, _can_assign: bool) {
         let str_slice = self.previous.string;
         // Skip leading and trailing '"' character
-        let value = Value::ValObjString(
-            self.string_table
-                .allocate_string_from_str(&str_slice[1..str_slice.len() - 1]),
-        );
-
-        self.emit_constant(value);
+        match self
+            .string_table
+            .allocate_string_from_str(&str_slice[1..str_slice.len() - 1])
+        {
+            Ok(string) => self.emit_constant(Value::ValObjString(string)),
+            Err(error) => self.error(&error.to_string()),
+        }
     }
 
     fn named_variable(&mut self, token: Token, can_assign: bool) {
@@ -285,12 +376,85 @@ impl<'a> Parser<'a> {
 
         if can_assign && self.match_token(TokenType::TokenEqual) {
             self.expression();
-            self.emit_bytes(opcode_u8!(OpSetGlobal), arg);
+
+            // Runtime errors should point at the variable, not the value.
+            self.emit_byte_at(opcode_u8!(OpSetGlobal), token.position());
+            self.emit_byte_at(arg, token.position());
         } else {
             self.emit_bytes(opcode_u8!(OpGetGlobal), arg);
         }
     }
 
+    // Compiles `a, b = b, a;`. Every value on the right is evaluated before
+    // any target is assigned, so swaps work without a temporary. It's only a
+    // statement, so a ',' after a name inside an expression (like a future
+    // argument list) never starts one.
+    fn multiple_assignment_statement(&mut self) {
+        let mut targets = Vec::new();
+
+        loop {
+            let arg = self.parse_variable("Expect variable name after ','.");
+            targets.push((arg, self.previous.position()));
+
+            if !self.match_token(TokenType::TokenComma) {
+                break;
+            }
+        }
+
+        self.consume(
+            TokenType::TokenEqual,
+            "Expect '=' after assignment targets.",
+        );
+
+        // Values are parsed above assignment precedence, so `a, b = b, a`
+        // doesn't see `b, a` as another list of targets.
+        let mut value_count = 0;
+        loop {
+            self.parse_precedence(Precedence::PrecOr);
+            value_count += 1;
+
+            // Assignment is a statement here, so `a, b = c = 1, 2;` can't
+            // chain it.
+            if self.match_token(TokenType::TokenEqual) {
+                self.error("Invalid assignment target.");
+                return;
+            }
+
+            if !self.match_token(TokenType::TokenComma) {
+                break;
+            }
+        }
+
+        if value_count != targets.len() {
+            self.error(
+                format!(
+                    "Expected {} values to assign but got {}.",
+                    targets.len(),
+                    value_count
+                )
+                .as_str(),
+            );
+            return;
+        }
+
+        // Check every target is defined before assigning any, so an undefined
+        // one stops the statement with nothing changed.
+        for (target, position) in &targets {
+            self.emit_byte_at(opcode_u8!(OpGetGlobal), *position);
+            self.emit_byte_at(*target, *position);
+            self.emit_byte_at(opcode_u8!(OpPop), *position);
+        }
+
+        // The last value is on top of the stack, so assign in reverse.
+        for (target, position) in targets.iter().rev() {
+            self.emit_byte_at(opcode_u8!(OpSetGlobal), *position);
+            self.emit_byte_at(*target, *position);
+            self.emit_byte(opcode_u8!(OpPop));
+        }
+
+        self.consume(TokenType::TokenSemicolon, "Expect ';' after value.");
+    }
+
     fn variable(&mut self, can_assign: bool) {
         let token = self.previous;
         self.named_variable(token, can_assign);
@@ -304,7 +468,7 @@ impl<'a> Parser<'a> {
     fn make_constant(&mut self, value: Value) -> u8 {
         let constant = self.current_chunk().add_constant(value);
 
-        if constant > std::u8::MAX as usize {
+        if constant > u8::MAX as usize {
             self.error("Too many constants in one chunk.");
             return 0;
         }
@@ -312,26 +476,98 @@ impl<'a> Parser<'a> {
         constant as u8
     }
 
-    fn grouping(&mut self, can_assign: bool) {
+    fn grouping(&mut self, _can_assign: bool) {
+        // Only recover from errors in the group itself, not earlier ones.
+        let already_panicking = self.panic_mode;
+        let error_count = self.diagnostics.len();
+
         self.expression();
+
+        if already_panicking {
+            self.consume(TokenType::TokenRightParen, "Expect ')' after expression.");
+            return;
+        }
+
+        if self.panic_mode {
+            // The error may have been reported at our own ')', when it's where
+            // an operand was expected, like `(1 + )`.
+            let at_closing_paren = self.previous.token_type == TokenType::TokenRightParen
+                && self.diagnostics.len() > error_count
+                && self.diagnostics[error_count].start == self.previous.start;
+
+            if at_closing_paren {
+                self.panic_mode = false;
+            } else {
+                self.synchronize_group();
+            }
+            return;
+        }
+
         self.consume(TokenType::TokenRightParen, "Expect ')' after expression.");
+
+        if self.panic_mode {
+            self.synchronize_group();
+        }
+    }
+
+    // Recovers from an error inside parentheses by skipping to the matching
+    // ')'. The rest of the statement is then parsed as normal, so a later
+    // mistake in it is reported too. If the statement ends first, recovery is
+    // left to `synchronize`.
+    fn synchronize_group(&mut self) {
+        if self.previous.token_type == TokenType::TokenSemicolon {
+            return;
+        }
+
+        let mut depth = 0;
+
+        while self.current.token_type != TokenType::TokenEof
+            && self.current.token_type != TokenType::TokenSemicolon
+        {
+            match self.current.token_type {
+                TokenType::TokenLeftParen => depth += 1,
+                TokenType::TokenRightParen if depth == 0 => {
+                    self.advance();
+                    self.panic_mode = false;
+                    return;
+                }
+                TokenType::TokenRightParen => depth -= 1,
+                _ => {}
+            }
+
+            self.advance();
+        }
     }
 
-    fn unary(&mut self, can_assign: bool) {
+    fn unary(&mut self, _can_assign: bool) {
         let operator_type = self.previous.token_type;
+        let position = self.previous.position();
 
         // Compile the operand.
         self.parse_precedence(Precedence::PrecUnary);
 
         // Emit the operator instruction.
         match operator_type {
-            TokenType::TokenBang => self.emit_byte(Opcodes::OpNot as u8),
-            TokenType::TokenMinus => self.emit_byte(Opcodes::OpNegate as u8),
+            TokenType::TokenBang => self.emit_byte_at(Opcodes::OpNot as u8, position),
+            TokenType::TokenMinus => self.emit_byte_at(Opcodes::OpNegate as u8, position),
             _ => unreachable!(),
         }
     }
 
     fn parse_precedence(&mut self, precedence: Precedence) {
+        // The parser recurses for each nested expression, so without a limit a
+        // long enough run of `(` or `-` overflows the stack.
+        if self.depth >= MAX_EXPRESSION_DEPTH {
+            self.error_at_current("Expression nesting too deep.");
+            return;
+        }
+
+        self.depth += 1;
+        self.parse_precedence_nested(precedence);
+        self.depth -= 1;
+    }
+
+    fn parse_precedence_nested(&mut self, precedence: Precedence) {
         self.advance();
 
         let prefix_rule_option = self.get_rule(self.previous.token_type).prefix;
@@ -341,7 +577,10 @@ impl<'a> Parser<'a> {
                 let can_assign = precedence <= Precedence::PrecAssignment;
                 prefix_rule(self, can_assign);
 
-                while precedence <= self.get_rule(self.current.token_type).precedence {
+                // Stop at an error, so recovery starts from where it was found.
+                while !self.panic_mode
+                    && precedence <= self.get_rule(self.current.token_type).precedence
+                {
                     self.advance();
                     // TODO - c parser doesn't check null for infix. This would be a parser rules bug (aka table is wrong).
                     let infix_rule = self.get_rule(self.previous.token_type).infix.unwrap();
@@ -359,8 +598,13 @@ impl<'a> Parser<'a> {
     }
 
     fn identifier_constant(&mut self, name: Token) -> u8 {
-        let obj = Value::ValObjString(self.string_table.allocate_string_from_str(name.string));
-        self.make_constant(obj)
+        match self.string_table.allocate_string_from_str(name.string) {
+            Ok(string) => self.make_constant(Value::ValObjString(string)),
+            Err(error) => {
+                self.error(&error.to_string());
+                0
+            }
+        }
     }
 
     fn parse_variable(&mut self, error_message: &str) -> u8 {
@@ -373,31 +617,36 @@ impl<'a> Parser<'a> {
         self.emit_bytes(opcode_u8!(OpDefineGlobal), global);
     }
 
-    fn binary(&mut self, can_assign: bool) {
+    fn binary(&mut self, _can_assign: bool) {
         // Remember the operator.
         let operator_type = self.previous.token_type;
+        let position = self.previous.position();
 
         // Compile the right operand.
         let rule = self.get_rule(operator_type);
         self.parse_precedence(rule.precedence.get_next_highest());
 
         // Emit the operator instruction.
-        match operator_type {
-            TokenType::TokenBangEqual => self.emit_bytes(opcode_u8!(OpEqual), opcode_u8!(OpNot)),
-            TokenType::TokenEqualEqual => self.emit_byte(opcode_u8!(OpEqual)),
-            TokenType::TokenGreater => self.emit_byte(opcode_u8!(OpGreater)),
-            TokenType::TokenGreaterEqual => self.emit_bytes(opcode_u8!(OpLess), opcode_u8!(OpNot)),
-            TokenType::TokenLess => self.emit_byte(opcode_u8!(OpLess)),
-            TokenType::TokenLessEqual => self.emit_bytes(opcode_u8!(OpGreater), opcode_u8!(OpNot)),
-            TokenType::TokenPlus => self.emit_byte(opcode_u8!(OpAdd)),
-            TokenType::TokenMinus => self.emit_byte(opcode_u8!(OpSubtract)),
-            TokenType::TokenStar => self.emit_byte(opcode_u8!(OpMultiply)),
-            TokenType::TokenSlash => self.emit_byte(opcode_u8!(OpDivide)),
+        let bytes: &[u8] = match operator_type {
+            TokenType::TokenBangEqual => &[opcode_u8!(OpEqual), opcode_u8!(OpNot)],
+            TokenType::TokenEqualEqual => &[opcode_u8!(OpEqual)],
+            TokenType::TokenGreater => &[opcode_u8!(OpGreater)],
+            TokenType::TokenGreaterEqual => &[opcode_u8!(OpLess), opcode_u8!(OpNot)],
+            TokenType::TokenLess => &[opcode_u8!(OpLess)],
+            TokenType::TokenLessEqual => &[opcode_u8!(OpGreater), opcode_u8!(OpNot)],
+            TokenType::TokenPlus => &[opcode_u8!(OpAdd)],
+            TokenType::TokenMinus => &[opcode_u8!(OpSubtract)],
+            TokenType::TokenStar => &[opcode_u8!(OpMultiply)],
+            TokenType::TokenSlash => &[opcode_u8!(OpDivide)],
             _ => unreachable!(),
+        };
+
+        for byte in bytes {
+            self.emit_byte_at(*byte, position);
         }
     }
 
-    fn literal(&mut self, can_assign: bool) {
+    fn literal(&mut self, _can_assign: bool) {
         match self.previous.token_type {
             TokenType::TokenFalse => self.emit_byte(opcode_u8!(OpFalse)),
             TokenType::TokenNil => self.emit_byte(opcode_u8!(OpNil)),
@@ -471,23 +720,342 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// How many errors `compile` reports by default before giving up. Past this
+/// point later errors are more likely to be confused by earlier ones.
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+// Deeper than any real expression, and shallow enough to parse on a thread
+// with a small stack.
+const MAX_EXPRESSION_DEPTH: usize = 256;
+
+/// Compiles a script, reporting every independent error found in it up to
+/// `max_errors`, or all of them if it's 0.
 pub fn compile(
     string_table: &mut LoxStringTable,
     source: &String,
+    max_errors: usize,
 ) -> Result<Chunk, InterpretError> {
-    let mut parser = Parser::new(string_table, source);
+    let mut parser = Parser::new(string_table, source, max_errors);
 
     parser.advance();
 
     while !parser.match_token(TokenType::TokenEof) {
+        if parser.reached_max_errors() {
+            break;
+        }
+
         parser.declaration();
     }
 
     parser.end_compiler();
 
     if parser.had_error {
-        return Err(InterpretError::InterpretCompileError);
+        return Err(InterpretError::InterpretCompileError(parser.diagnostics));
+    }
+
+    Ok(parser.compiling_chunk)
+}
+
+/// Compiles a lone expression, like `1 + 2`, into a chunk that prints its
+/// value. The repl uses this to show the result of what's typed at it.
+pub fn compile_expression(
+    string_table: &mut LoxStringTable,
+    source: &String,
+    max_errors: usize,
+) -> Result<Chunk, InterpretError> {
+    let mut parser = Parser::new(string_table, source, max_errors);
+
+    parser.advance();
+    parser.expression();
+    parser.consume(TokenType::TokenEof, "Expect end of expression.");
+    parser.emit_byte(opcode_u8!(OpPrint));
+    parser.end_compiler();
+
+    if parser.had_error {
+        return Err(InterpretError::InterpretCompileError(parser.diagnostics));
     }
 
     Ok(parser.compiling_chunk)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::compile;
+    use super::compile_expression;
+    use super::DEFAULT_MAX_ERRORS;
+    use crate::diagnostic::Diagnostic;
+    use crate::diagnostic::Severity;
+    use crate::lox_string_table::LoxStringTable;
+    use crate::vm::InterpretError;
+
+    fn compile_errors(source: &str) -> Vec<Diagnostic> {
+        let mut table = LoxStringTable::new();
+
+        match compile(&mut table, &String::from(source), DEFAULT_MAX_ERRORS) {
+            Err(InterpretError::InterpretCompileError(diagnostics)) => diagnostics,
+            _ => panic!("expected a compile error"),
+        }
+    }
+
+    #[test]
+    fn diagnostic_fields_test() {
+        let diagnostics = compile_errors("print 1;\nprint 1 +;");
+
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                severity: Severity::Error,
+                message: String::from("Expect expression."),
+                line: 2,
+                column: 10,
+                start: 18,
+                end: 19,
+                token: Some(String::from(";")),
+                note: None,
+            }]
+        );
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "[line 2] Error at ';': Expect expression."
+        );
+    }
+
+    #[test]
+    fn diagnostic_at_end_and_scanner_test() {
+        let diagnostics = compile_errors("print 1");
+        assert_eq!(diagnostics[0].token, Some(String::new()));
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "[line 1] Error at end: Expect ';' after value."
+        );
+
+        let diagnostics = compile_errors("print \"abc");
+        assert_eq!(diagnostics[0].token, None);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "[line 1] Error: Unterminated string."
+        );
+    }
+
+    #[test]
+    fn keyword_typo_test() {
+        let diagnostics = compile_errors("fucn foo;\npritn 1;\nvra x = 1;");
+
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(
+            diagnostics[0].note,
+            Some(String::from("did you mean 'fun'?"))
+        );
+        assert_eq!(
+            diagnostics[1].note,
+            Some(String::from("did you mean 'print'?"))
+        );
+        assert_eq!(
+            diagnostics[2].note,
+            Some(String::from("did you mean 'var'?"))
+        );
+
+        // Only a lone identifier is a likely typo.
+        let diagnostics = compile_errors("i + ;");
+        assert_eq!(diagnostics[0].note, None);
+    }
+
+    #[test]
+    fn diagnostics_after_synchronize_test() {
+        let diagnostics = compile_errors("var = 1;\nprint 2 +;\nprint 3;");
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].line, 1);
+        assert_eq!(diagnostics[1].line, 2);
+    }
+
+    #[test]
+    fn bytecode_test() {
+        let mut table = LoxStringTable::new();
+        let source = String::from("var a = 1;\na = -a * 2;");
+        let chunk = compile(&mut table, &source, DEFAULT_MAX_ERRORS)
+            .ok()
+            .unwrap();
+
+        assert_eq!(
+            chunk.disassemble_to_string("code"),
+            "== code ==\n\
+             0000    1 OP_CONSTANT      0001 '1'\n\
+             0002    | OP_DEFINE_GLOBAL 0000 'a'\n\
+             0004    2 OP_GET_GLOBAL    0003 'a'\n\
+             0006    | OP_NEGATE\n\
+             0007    | OP_CONSTANT      0004 '2'\n\
+             0009    | OP_MULTIPLY\n\
+             0010    | OP_SET_GLOBAL    0002 'a'\n\
+             0012    | OP_POP\n\
+             0013    | OP_RETURN\n"
+        );
+    }
+
+    #[test]
+    fn multiple_assignment_test() {
+        let mut table = LoxStringTable::new();
+        let source = String::from("a, b = b, a;");
+        let chunk = compile(&mut table, &source, DEFAULT_MAX_ERRORS)
+            .ok()
+            .unwrap();
+
+        assert_eq!(
+            chunk.disassemble_to_string("code"),
+            "== code ==\n\
+             0000    1 OP_GET_GLOBAL    0002 'b'\n\
+             0002    | OP_GET_GLOBAL    0003 'a'\n\
+             0004    | OP_GET_GLOBAL    0000 'a'\n\
+             0006    | OP_POP\n\
+             0007    | OP_GET_GLOBAL    0001 'b'\n\
+             0009    | OP_POP\n\
+             0010    | OP_SET_GLOBAL    0001 'b'\n\
+             0012    | OP_POP\n\
+             0013    | OP_SET_GLOBAL    0000 'a'\n\
+             0015    | OP_POP\n\
+             0016    | OP_RETURN\n"
+        );
+    }
+
+    #[test]
+    fn multiple_assignment_errors_test() {
+        let diagnostics = compile_errors(
+            "a, b = 1;\n\
+             a, b = 1, 2, 3;\n\
+             a, 1 = 2, 3;\n\
+             a, b;\n\
+             print a, b;\n\
+             print (a, b = 1, 2);\n\
+             a, b = c = 1, 2;",
+        );
+
+        assert_eq!(
+            messages(&diagnostics),
+            vec![
+                "[line 1] Error at '1': Expected 2 values to assign but got 1.",
+                "[line 2] Error at '3': Expected 2 values to assign but got 3.",
+                "[line 3] Error at '1': Expect variable name after ','.",
+                "[line 4] Error at ';': Expect '=' after assignment targets.",
+                "[line 5] Error at ',': Expect ';' after value.",
+                "[line 6] Error at ',': Expect ')' after expression.",
+                "[line 7] Error at '=': Invalid assignment target.",
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_expression_test() {
+        let mut table = LoxStringTable::new();
+        let expression = |table: &mut LoxStringTable, source: &str| {
+            compile_expression(table, &String::from(source), DEFAULT_MAX_ERRORS)
+                .ok()
+                .map(|chunk| chunk.disassemble_to_string("code"))
+        };
+
+        assert_eq!(
+            expression(&mut table, "1 + 2").unwrap(),
+            "== code ==\n\
+             0000    1 OP_CONSTANT      0000 '1'\n\
+             0002    | OP_CONSTANT      0001 '2'\n\
+             0004    | OP_ADD\n\
+             0005    | OP_PRINT\n\
+             0006    | OP_RETURN\n"
+        );
+        assert!(expression(&mut table, "a = 3").is_some());
+        assert!(expression(&mut table, "1 + 2;").is_none());
+        assert!(expression(&mut table, "var a = 1").is_none());
+        assert!(expression(&mut table, "print 1").is_none());
+    }
+
+    fn messages(diagnostics: &[Diagnostic]) -> Vec<String> {
+        diagnostics.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn recover_in_parentheses_test() {
+        let diagnostics = compile_errors("print (1 + ) + ((2 *)) + (3 4);");
+
+        assert_eq!(
+            messages(&diagnostics),
+            vec![
+                "[line 1] Error at ')': Expect expression.",
+                "[line 1] Error at ')': Expect expression.",
+                "[line 1] Error at '4': Expect ')' after expression.",
+            ]
+        );
+
+        // A group that's never closed leaves recovery to the statement.
+        let diagnostics = compile_errors("print (1 + ;\nprint -;");
+        assert_eq!(
+            messages(&diagnostics),
+            vec![
+                "[line 1] Error at ';': Expect expression.",
+                "[line 2] Error at ';': Expect expression.",
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_string_test() {
+        let diagnostics = compile_errors("print 1;\nprint \"abc;\nprint 2;");
+
+        // Reported where the string starts, not at the end of the file.
+        assert_eq!(
+            messages(&diagnostics),
+            vec!["[line 2] Error: Unterminated string."]
+        );
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (2, 7));
+    }
+
+    #[test]
+    fn max_errors_test() {
+        let source = "print +;\n".repeat(10);
+        let mut table = LoxStringTable::new();
+
+        match compile(&mut table, &source, 3) {
+            Err(InterpretError::InterpretCompileError(diagnostics)) => {
+                assert_eq!(diagnostics.len(), 3);
+                assert_eq!(diagnostics[2].line, 3);
+            }
+            _ => panic!("expected a compile error"),
+        }
+
+        assert_eq!(compile_errors(&source).len(), 10);
+
+        // A limit of 0 is no limit, rather than stopping before the first
+        // error and compiling nothing.
+        match compile(&mut table, &source, 0) {
+            Err(InterpretError::InterpretCompileError(diagnostics)) => {
+                assert_eq!(diagnostics.len(), 10);
+            }
+            _ => panic!("expected a compile error"),
+        }
+        match compile_expression(&mut table, &String::from("+"), 0) {
+            Err(InterpretError::InterpretCompileError(diagnostics)) => {
+                assert_eq!(diagnostics.len(), 1);
+            }
+            _ => panic!("expected a compile error"),
+        }
+    }
+
+    #[test]
+    fn nesting_too_deep_test() {
+        let nested = |depth: usize| format!("print {}1{};", "(".repeat(depth), ")".repeat(depth));
+        let mut table = LoxStringTable::new();
+        assert!(compile(&mut table, &nested(255), DEFAULT_MAX_ERRORS).is_ok());
+
+        let source = format!(
+            "{}\nprint {}1;\nprint +;",
+            nested(100_000),
+            "-".repeat(100_000)
+        );
+        assert_eq!(
+            messages(&compile_errors(&source)),
+            vec![
+                "[line 1] Error at '(': Expression nesting too deep.",
+                "[line 2] Error at '-': Expression nesting too deep.",
+                "[line 3] Error at '+': Expect expression.",
+            ]
+        );
+    }
+}
@@ -1,2 +1,36 @@
-pub const DEBUG_PRINT_CODE: bool = true;
-pub const DEBUG_TRACE_EXECUTION: bool = true;
+use std::fs;
+use std::io;
+
+/// Whether debug output is compiled in. It always is in debug builds, and in
+/// release builds only with the `debug-output` feature, otherwise the checks
+/// for it are removed entirely.
+pub const DEBUG_OUTPUT_ENABLED: bool = cfg!(any(debug_assertions, feature = "debug-output"));
+
+/// Debug output a VM can be asked for. Everything is off by default, and is
+/// written to stderr unless another output is chosen, so it doesn't get mixed
+/// up with what the script prints.
+pub struct DebugOptions {
+    /// Disassemble each chunk after compiling it.
+    pub dump_bytecode: bool,
+    /// Print the stack and each instruction as it's executed.
+    pub trace_execution: bool,
+    pub output: Box<dyn io::Write + Send>,
+}
+
+impl DebugOptions {
+    /// Writes debug output to the file at `path`, replacing it if it exists.
+    pub fn output_to_file(&mut self, path: &str) -> io::Result<()> {
+        self.output = Box::new(io::BufWriter::new(fs::File::create(path)?));
+        Ok(())
+    }
+}
+
+impl Default for DebugOptions {
+    fn default() -> Self {
+        DebugOptions {
+            dump_bytecode: false,
+            trace_execution: false,
+            output: Box::new(io::stderr()),
+        }
+    }
+}
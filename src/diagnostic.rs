@@ -0,0 +1,304 @@
+use std::fmt;
+use std::fmt::Write;
+use std::io;
+use std::io::IsTerminal;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    // Nothing emits warnings yet, but consumers should be ready for them.
+    #[allow(dead_code)]
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+/// A problem found while compiling or running a script. These are collected
+/// instead of printed so that callers can decide how to show them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub line: i32,
+    /// 1-based column of the offending token.
+    pub column: i32,
+    /// Byte offsets of the offending token in the source.
+    pub start: usize,
+    pub end: usize,
+    /// The text of the offending token. This is empty for the end of the file,
+    /// and `None` for errors reported by the scanner, where the message already
+    /// describes the bad input.
+    pub token: Option<String>,
+    pub note: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    // Matches the book's `[line 1] Error at 'x': message` format.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] {}", self.line, self.severity)?;
+
+        match &self.token {
+            Some(token) if token.is_empty() => write!(f, " at end")?,
+            Some(token) => write!(f, " at '{}'", token)?,
+            None => {}
+        }
+
+        write!(f, ": {}", self.message)?;
+
+        if let Some(note) = &self.note {
+            write!(f, "\n  note: {}", note)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Escapes a string for use inside a JSON string literal.
+fn json_escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// ANSI escapes used when rendering to a terminal.
+const BOLD_RED: &str = "\x1b[1;31m";
+const BOLD_YELLOW: &str = "\x1b[1;33m";
+const BOLD_BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether diagnostics written to stderr should be colored.
+pub fn stderr_supports_color() -> bool {
+    io::stderr().is_terminal()
+}
+
+impl Diagnostic {
+    /// Renders the diagnostic rustc style, with the offending source line and
+    /// the span underlined:
+    ///
+    /// ```text
+    /// error: Expect expression.
+    ///  --> test.lox:2:10
+    ///   |
+    /// 2 | print 1 +;
+    ///   |          ^
+    /// ```
+    pub fn render(&self, source_name: &str, source: &str, color: bool) -> String {
+        let paint = |code: &'static str| if color { code } else { "" };
+        let reset = paint(RESET);
+
+        let (severity, severity_color) = match self.severity {
+            Severity::Error => ("error", paint(BOLD_RED)),
+            Severity::Warning => ("warning", paint(BOLD_YELLOW)),
+        };
+        let blue = paint(BOLD_BLUE);
+
+        // The snippet is found from the span rather than the line, as tokens
+        // spanning lines (like strings) report the line they end on.
+        let start = self.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line_text = source[line_start..line_end].trim_end_matches('\r');
+        let line_number = source[..line_start].matches('\n').count() + 1;
+        let column = source[line_start..start].chars().count() + 1;
+
+        // Underline the span, up to the end of the first line. Empty spans,
+        // like the end of the file, still get a single caret.
+        let end = self.end.max(start).min(line_end);
+        let underline = source[start..end].chars().count().max(1);
+
+        // Reuse tabs from the source line so the carets line up.
+        let padding: String = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        let gutter = " ".repeat(line_number.to_string().len());
+        let mut out = String::new();
+
+        // Writing to a String can't fail.
+        let _ = writeln!(
+            out,
+            "{}{}{}: {}{}{}",
+            severity_color,
+            severity,
+            reset,
+            paint(BOLD),
+            self.message,
+            reset
+        );
+        let _ = writeln!(
+            out,
+            "{}{}-->{} {}:{}:{}",
+            gutter, blue, reset, source_name, line_number, column
+        );
+        let _ = writeln!(out, "{} {}|{}", gutter, blue, reset);
+        let _ = writeln!(out, "{}{} |{} {}", blue, line_number, reset, line_text);
+        let _ = writeln!(
+            out,
+            "{} {}|{} {}{}{}{}",
+            gutter,
+            blue,
+            reset,
+            padding,
+            severity_color,
+            "^".repeat(underline),
+            reset
+        );
+
+        if let Some(note) = &self.note {
+            let _ = writeln!(
+                out,
+                "{} {}={} {}help{}: {}",
+                gutter,
+                blue,
+                reset,
+                paint(BOLD),
+                reset,
+                note
+            );
+        }
+
+        out
+    }
+
+    /// Renders the diagnostic as a single line JSON object, for tools that
+    /// consume errors. `kind` says what sort of error this is, like
+    /// `compile_error` or `type_error`.
+    pub fn to_json(&self, kind: &str, source_name: &str) -> String {
+        let note = match &self.note {
+            Some(note) => format!("\"{}\"", json_escape(note)),
+            None => String::from("null"),
+        };
+
+        format!(
+            "{{\"kind\":\"{}\",\"message\":\"{}\",\"file\":\"{}\",\"line\":{},\"column\":{},\"span\":{{\"start\":{},\"end\":{}}},\"note\":{}}}",
+            json_escape(kind),
+            json_escape(&self.message),
+            json_escape(source_name),
+            self.line,
+            self.column,
+            self.start,
+            self.end,
+            note
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Diagnostic;
+    use super::Severity;
+
+    fn diagnostic(line: i32, start: usize, end: usize) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: String::from("Expect expression."),
+            line,
+            column: 0,
+            start,
+            end,
+            token: None,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn render_test() {
+        let source = "print 1;\nprint 1 +;\n";
+
+        assert_eq!(
+            diagnostic(2, 18, 19).render("test.lox", source, false),
+            "error: Expect expression.\n \
+             --> test.lox:2:10\n  \
+               |\n\
+             2 | print 1 +;\n  \
+               |          ^\n"
+        );
+    }
+
+    #[test]
+    fn render_note_and_tabs_test() {
+        let source = "\tprint abc;";
+        let mut diagnostic = diagnostic(1, 7, 10);
+        diagnostic.note = Some(String::from("did you mean 'abd'?"));
+
+        assert_eq!(
+            diagnostic.render("x.lox", source, false),
+            "error: Expect expression.\n \
+             --> x.lox:1:8\n  \
+               |\n\
+             1 | \tprint abc;\n  \
+               | \t      ^^^\n  \
+               = help: did you mean 'abd'?\n"
+        );
+    }
+
+    #[test]
+    fn render_multi_line_span_test() {
+        // An unterminated string runs to the end of the file, but only the
+        // first line is underlined.
+        let source = "print \"abc\ndef";
+        let diagnostic = diagnostic(2, 6, source.len());
+
+        assert_eq!(
+            diagnostic.render("x.lox", source, false),
+            "error: Expect expression.\n \
+             --> x.lox:1:7\n  \
+               |\n\
+             1 | print \"abc\n  \
+               |       ^^^^\n"
+        );
+    }
+
+    #[test]
+    fn json_test() {
+        let mut diagnostic = diagnostic(2, 18, 19);
+        diagnostic.column = 10;
+        diagnostic.message = String::from("Undefined variable 'a\"b'.");
+
+        assert_eq!(
+            diagnostic.to_json("undefined_variable", "dir\\x.lox"),
+            "{\"kind\":\"undefined_variable\",\"message\":\"Undefined variable 'a\\\"b'.\",\
+             \"file\":\"dir\\\\x.lox\",\"line\":2,\"column\":10,\
+             \"span\":{\"start\":18,\"end\":19},\"note\":null}"
+        );
+
+        diagnostic.note = Some(String::from("line one\n\tline two\u{1}"));
+        assert!(diagnostic
+            .to_json("compile_error", "x.lox")
+            .ends_with("\"note\":\"line one\\n\\tline two\\u0001\"}"));
+    }
+
+    #[test]
+    fn render_end_of_file_test() {
+        let source = "print 1";
+
+        assert!(diagnostic(1, 7, 7)
+            .render("x.lox", source, false)
+            .ends_with("1 | print 1\n  |        ^\n"));
+    }
+}
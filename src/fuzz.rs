@@ -0,0 +1,407 @@
+// Generates random Lox programs from a seed and checks that nothing panics on
+// them, and that running one gives the same result however it gets to the VM:
+// straight from source, through a `.loxc` round trip, or through the
+// disassembler and assembler. About a quarter of the programs are mangled
+// first, to exercise the error paths too.
+//
+// Every program comes from its seed alone, so a failure can be reproduced with
+// `rlox fuzz --seed=N --count=1`.
+
+use crate::chunk::Chunk;
+use crate::compiler;
+use crate::lox_string_table::LoxStringTable;
+use crate::loxc;
+use crate::scanner::Scanner;
+use crate::scanner::TokenType;
+use crate::vm::InterpretError;
+use crate::vm::SharedOutput;
+use crate::vm::VmLimits;
+use crate::vm::VM;
+use std::panic;
+
+// A xorshift64* generator. It doesn't need to be good, just quick and the
+// same everywhere, so a seed always gives the same program.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Spread the seed out (splitmix64), as xorshift gets stuck on zero and
+        // starts off poorly mixed for small seeds.
+        let mut state = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        state ^= state >> 31;
+
+        Rng(if state == 0 { 1 } else { state })
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        return self.0.wrapping_mul(0x2545_f491_4f6c_dd1d);
+    }
+
+    // A number in 0..n.
+    fn below(&mut self, n: usize) -> usize {
+        return (self.next() % n as u64) as usize;
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        return self.below(100) < percent;
+    }
+
+    fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+        return choices[self.below(choices.len())];
+    }
+}
+
+// Programs start by setting the number variables, and mostly only assign
+// numbers to them, so most statements don't stop at an undefined variable or a
+// type error before the rest of the program has run.
+const NUMBER_NAMES: [&str; 2] = ["a", "b"];
+const OTHER_NAMES: [&str; 2] = ["c", "value"];
+const NAMES: [&str; 4] = ["a", "b", "c", "value"];
+
+const ARITHMETIC_OPERATORS: [&str; 4] = ["+", "-", "*", "/"];
+const BINARY_OPERATORS: [&str; 10] = ["+", "-", "*", "/", "==", "!=", "<", "<=", ">", ">="];
+
+const STRINGS: [&str; 6] = ["", "a", "lox", "two words", "line\nbreak", "ünïcödé ✓"];
+
+const NUMBERS: [&str; 8] = [
+    "0",
+    "1",
+    "2",
+    "10",
+    "0.5",
+    "3.25",
+    "1000000",
+    "123456789012345678901234567890",
+];
+
+// What mangling inserts into a program.
+const FRAGMENTS: [&str; 16] = [
+    "(", ")", ";", "\"", "=", "+", "!", "var", "print", "nil", "@", "é", "\n", "//", "1.", "\0",
+];
+
+const MAX_DEPTH: usize = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Flavour {
+    Numbers,  // Arithmetic on numbers, which always runs.
+    Anything, // Any mix of values and operators, which often fails.
+}
+
+// A name to assign to, now and then one that breaks the flavour's rules.
+fn target(rng: &mut Rng, flavour: Flavour) -> &'static str {
+    match flavour {
+        _ if rng.chance(3) => rng.pick(&NAMES),
+        Flavour::Numbers => rng.pick(&NUMBER_NAMES),
+        Flavour::Anything => rng.pick(&OTHER_NAMES),
+    }
+}
+
+fn expression(rng: &mut Rng, flavour: Flavour, depth: usize, out: &mut String) {
+    if depth >= MAX_DEPTH || rng.chance(30) {
+        match (flavour, rng.below(5)) {
+            (Flavour::Numbers, 0..=2) | (Flavour::Anything, 0) => out.push_str(rng.pick(&NUMBERS)),
+            (Flavour::Numbers, _) => out.push_str(rng.pick(&NUMBER_NAMES)),
+            (Flavour::Anything, 1) => {
+                out.push('"');
+                out.push_str(rng.pick(&STRINGS));
+                out.push('"');
+            }
+            (Flavour::Anything, 2) => out.push_str(rng.pick(&["true", "false", "nil"])),
+            (Flavour::Anything, _) => out.push_str(rng.pick(&NAMES)),
+        }
+        return;
+    }
+
+    match rng.below(6) {
+        0 => {
+            match flavour {
+                Flavour::Numbers => out.push('-'),
+                Flavour::Anything => out.push_str(rng.pick(&["-", "!"])),
+            }
+            expression(rng, flavour, depth + 1, out);
+        }
+        1 => {
+            out.push('(');
+            expression(rng, flavour, depth + 1, out);
+            out.push(')');
+        }
+        // Parenthesized, as an assignment can't be the operand of anything.
+        2 => {
+            out.push('(');
+            out.push_str(target(rng, flavour));
+            out.push_str(" = ");
+            expression(rng, flavour, depth + 1, out);
+            out.push(')');
+        }
+        _ => {
+            expression(rng, flavour, depth + 1, out);
+            out.push(' ');
+            match flavour {
+                Flavour::Numbers => out.push_str(rng.pick(&ARITHMETIC_OPERATORS)),
+                Flavour::Anything => out.push_str(rng.pick(&BINARY_OPERATORS)),
+            }
+            out.push(' ');
+            expression(rng, flavour, depth + 1, out);
+        }
+    }
+}
+
+fn statement(rng: &mut Rng, out: &mut String) {
+    let flavour = if rng.chance(70) {
+        Flavour::Numbers
+    } else {
+        Flavour::Anything
+    };
+
+    match rng.below(8) {
+        0 => {
+            out.push_str("var ");
+            out.push_str(rng.pick(&OTHER_NAMES));
+            out.push(';');
+        }
+        1 | 2 => {
+            out.push_str("var ");
+            out.push_str(target(rng, flavour));
+            out.push_str(" = ");
+            expression(rng, flavour, 0, out);
+            out.push(';');
+        }
+        3 => {
+            out.push_str(target(rng, flavour));
+            out.push_str(" = ");
+            expression(rng, flavour, 0, out);
+            out.push(';');
+        }
+        4 => {
+            expression(rng, flavour, 0, out);
+            out.push(';');
+        }
+        _ => {
+            out.push_str("print ");
+            expression(rng, flavour, 0, out);
+            out.push(';');
+        }
+    }
+
+    if rng.chance(10) {
+        out.push_str(" // a comment");
+    }
+    out.push('\n');
+}
+
+// Deletes, inserts, duplicates or cuts off characters, which mostly makes the
+// program invalid.
+fn mangle(rng: &mut Rng, source: &str) -> String {
+    let mut chars: Vec<char> = source.chars().collect();
+
+    for _ in 0..1 + rng.below(3) {
+        let at = rng.below(chars.len() + 1);
+        match rng.below(4) {
+            0 if at < chars.len() => {
+                chars.remove(at);
+            }
+            1 => {
+                let fragment = rng.pick(&FRAGMENTS);
+                chars.splice(at..at, fragment.chars());
+            }
+            2 => {
+                let end = (at + 1 + rng.below(8)).min(chars.len());
+                let copy: Vec<char> = chars[at..end].to_vec();
+                chars.splice(at..at, copy);
+            }
+            _ => chars.truncate(at),
+        }
+    }
+
+    return chars.into_iter().collect();
+}
+
+/// The program for a seed.
+pub fn generate(seed: u64) -> String {
+    let mut rng = Rng::new(seed);
+    let mut source = String::from("var a = 1;\nvar b = 2;\n");
+
+    for _ in 0..1 + rng.below(12) {
+        statement(&mut rng, &mut source);
+    }
+
+    if rng.chance(25) {
+        source = mangle(&mut rng, &source);
+    }
+
+    return source;
+}
+
+// Limits keep the VMs from running away, like a string doubled on every line.
+fn new_vm(output: &SharedOutput) -> VM {
+    let mut vm = VM::with_limits(VmLimits {
+        max_stack_size: Some(1024),
+        max_instructions: Some(1_000_000),
+        max_string_bytes: Some(1 << 20),
+        max_globals: None,
+    });
+    vm.set_output(Box::new(output.clone()));
+    return vm;
+}
+
+// What a run printed and how it ended, to compare between runs.
+fn outcome(result: Result<(), InterpretError>, output: &SharedOutput) -> String {
+    let ending = match result {
+        Ok(()) => "ok".to_string(),
+        Err(InterpretError::InterpretCompileError(diagnostics)) => diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        // Only the line, as assembled bytecode doesn't keep columns.
+        Err(InterpretError::InterpretRuntimeError(error)) => {
+            format!("[line {}] {}", error.position.line, error.message)
+        }
+    };
+
+    return format!("{}-> {}", output.contents(), ending);
+}
+
+// Runs a chunk loaded into a fresh VM by `load`.
+fn run_loaded(load: impl FnOnce(&mut VM) -> Result<Chunk, String>) -> String {
+    let output = SharedOutput::default();
+    let mut vm = new_vm(&output);
+
+    match load(&mut vm) {
+        Ok(chunk) => outcome(vm.interpret_chunk(chunk), &output),
+        Err(error) => format!("couldn't load: {}", error),
+    }
+}
+
+fn compare(what: &str, expected: &str, actual: &str) -> Result<(), String> {
+    if expected != actual {
+        return Err(format!(
+            "{} differs.\n  expected: {:?}\n  actual:   {:?}",
+            what, expected, actual
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_source(source: &String, rng: &mut Rng) -> Result<(), String> {
+    // Every character is consumed by some token, so the scanner has to reach
+    // the end in at most that many tokens.
+    let mut scanner = Scanner::new(source);
+    let mut tokens = 0;
+    while scanner.scan_token().token_type != TokenType::TokenEof {
+        tokens += 1;
+        if tokens > source.len() {
+            return Err("The scanner never reached the end.".to_string());
+        }
+    }
+
+    let output = SharedOutput::default();
+    let mut vm = new_vm(&output);
+    let expected = outcome(vm.interpret(source), &output);
+
+    let mut string_table = LoxStringTable::new();
+    let chunk = match compiler::compile(&mut string_table, source, compiler::DEFAULT_MAX_ERRORS) {
+        Ok(chunk) => chunk,
+        Err(error) => {
+            let actual = outcome(Err(error), &SharedOutput::default());
+            return compare("Compiling on its own", &expected, &actual);
+        }
+    };
+
+    let saved = loxc::save(&chunk);
+    let actual = run_loaded(|vm| vm.load_chunk(&saved).map_err(|error| error.to_string()));
+    compare("Running the .loxc round trip", &expected, &actual)?;
+
+    let text = chunk.disassemble_to_string("fuzz");
+    let actual = run_loaded(|vm| vm.assemble_chunk(&text).map_err(|error| error.to_string()));
+    compare("Running the disassembly", &expected, &actual)?;
+
+    // A damaged `.loxc` file has to be rejected by the loader or the verifier,
+    // or run safely, whatever it does.
+    let mut damaged = saved;
+    for _ in 0..1 + rng.below(4) {
+        let at = rng.below(damaged.len());
+        damaged[at] = rng.next() as u8;
+    }
+    run_loaded(|vm| vm.load_chunk(&damaged).map_err(|error| error.to_string()));
+
+    Ok(())
+}
+
+/// Generates the program for a seed and checks it, returning what went wrong.
+pub fn check(seed: u64) -> Result<(), String> {
+    let source = generate(seed);
+    // A different stream from the one the program came from.
+    let mut rng = Rng::new(!seed);
+
+    match panic::catch_unwind(panic::AssertUnwindSafe(|| check_source(&source, &mut rng))) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = match (
+                payload.downcast_ref::<&str>(),
+                payload.downcast_ref::<String>(),
+            ) {
+                (Some(message), _) => message.to_string(),
+                (_, Some(message)) => message.clone(),
+                _ => "unknown panic".to_string(),
+            };
+            Err(format!("Panicked: {}", message))
+        }
+    }
+}
+
+/// Checks the programs for `count` seeds from `first_seed` on, printing each
+/// failure with its program. Returns whether they all passed.
+pub fn fuzz(first_seed: u64, count: u64) -> bool {
+    let mut failed = 0;
+
+    for seed in (0..count).map(|index| first_seed.wrapping_add(index)) {
+        if let Err(message) = check(seed) {
+            failed += 1;
+            println!("FAIL seed {}: {}", seed, message);
+            for line in generate(seed).lines() {
+                println!("    | {}", line);
+            }
+        }
+    }
+
+    println!(
+        "Checked {} programs from seed {}, {} failed.",
+        count, first_seed, failed
+    );
+
+    return failed == 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check;
+    use super::generate;
+    use super::mangle;
+    use super::Rng;
+
+    #[test]
+    fn generate_test() {
+        assert_eq!(generate(42), generate(42));
+        assert_ne!(generate(1), generate(2));
+
+        let mut rng = Rng::new(0);
+        assert_ne!(rng.next(), rng.next());
+        assert!(mangle(&mut rng, "print 1;\n").len() < 50);
+    }
+
+    #[test]
+    fn fuzz_test() {
+        for seed in 0..300 {
+            if let Err(message) = check(seed) {
+                panic!("seed {}: {}\n{}", seed, message, generate(seed));
+            }
+        }
+    }
+}
@@ -49,7 +49,7 @@ impl PartialEq for InternalStringEntry {
 // This allows us to search the hashmap using &str instead of InternalStringEntries.
 impl Borrow<str> for Box<InternalStringEntry> {
     fn borrow(&self) -> &str {
-        &*self
+        self
     }
 }
 
@@ -140,7 +140,7 @@ impl Drop for LoxString {
     // We know the pointer is valid if non-null, because the table holds a
     // Box<InternalStringEntry>, which means it will not move in memory.
     fn drop(&mut self) {
-        if self.entry.get() != ptr::null() {
+        if !self.entry.get().is_null() {
             // println!("dropping loxstring for {}", self.as_str());
             unsafe {
                 *(*(self.entry.get() as *mut InternalStringEntry))
@@ -179,15 +179,62 @@ impl Drop for LoxString {
 // will only be removed by the gc.
 pub struct LoxStringTable {
     table: HashSet<Box<InternalStringEntry>>,
+    bytes: usize,             // Total length of every string in the table.
+    max_bytes: Option<usize>, // The most `bytes` can grow to, if limited.
+}
+
+/// Returned when interning a new string would take the table past its limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StringLimitError {
+    pub max_bytes: usize,
+}
+
+impl fmt::Display for StringLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Exceeded the limit of {} bytes of strings.",
+            self.max_bytes
+        )
+    }
 }
 
 impl LoxStringTable {
     pub fn new() -> Self {
         Self {
             table: HashSet::new(),
+            bytes: 0,
+            max_bytes: None,
         }
     }
 
+    // The total length in bytes of every interned string. Only tests read it,
+    // as the table enforces its limit itself.
+    #[allow(dead_code)]
+    pub fn total_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // Limits the total length of the interned strings. Every way of adding a
+    // string goes through the table, so this covers literals, loaded chunks
+    // and strings made at runtime alike. Strings already interned don't count
+    // again.
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) {
+        self.max_bytes = max_bytes;
+    }
+
+    // Accounts for a new string of `length` bytes, if it fits.
+    fn reserve(&mut self, length: usize) -> Result<(), StringLimitError> {
+        if let Some(max_bytes) = self.max_bytes {
+            if self.bytes + length > max_bytes {
+                return Err(StringLimitError { max_bytes });
+            }
+        }
+
+        self.bytes += length;
+        Ok(())
+    }
+
     // TODO - probably a std lib implementation of FNV-1a using hasher
     fn hash_string(string: &str) -> u32 {
         let mut hash = Wrapping(2166136261u32);
@@ -209,9 +256,13 @@ impl LoxStringTable {
     }
 
     // Allocate a string from a non owning slice.
-    pub fn allocate_string_from_str(&mut self, string: &str) -> LoxString {
+    pub fn allocate_string_from_str(
+        &mut self,
+        string: &str,
+    ) -> Result<LoxString, StringLimitError> {
         // Insert a new Rc if we don't have one already.
         if !self.table.contains(string) {
+            self.reserve(string.len())?;
             self.table
                 .insert(LoxStringTable::make_new_string_entry(string.into()));
         }
@@ -226,22 +277,26 @@ impl LoxStringTable {
             *internal_string.refcount.borrow_mut() += 1;
         }
 
-        LoxString {
+        Ok(LoxString {
             entry: Cell::new(internal_string.as_ref()),
             string: internal_string.data.as_ref() as *const str,
-        }
+        })
     }
 
     // Allocate a string from an existing Box<str>.
     //
     // If the string is not interned, we keep the Box. If it's already interned, we
     // return a LoxString with the Box held by the table.
-    fn allocate_string_from_box(&mut self, box_string: Box<str>) -> LoxString {
+    fn allocate_string_from_box(
+        &mut self,
+        box_string: Box<str>,
+    ) -> Result<LoxString, StringLimitError> {
         // See if we need to insert this Box<str> or not. Hold a pointer to the string
         // because we use it for lookup later, but the box itself becomes invalid if we
         // insert it.
         let string: *const str = box_string.as_ref();
         if !self.table.contains(box_string.as_ref()) {
+            self.reserve(box_string.len())?;
             self.table
                 .insert(LoxStringTable::make_new_string_entry(box_string));
         }
@@ -258,10 +313,10 @@ impl LoxStringTable {
             *internal_string.refcount.borrow_mut() += 1;
         }
 
-        LoxString {
+        Ok(LoxString {
             entry: Cell::new(internal_string.as_ref()),
             string: internal_string.data.as_ref() as *const str,
-        }
+        })
     }
 
     // Concatenate two strings, returning a new LoxString.
@@ -270,7 +325,11 @@ impl LoxStringTable {
     // both strings (creating hash from both). However, that would require either a custom hashtable,
     // or some fancy impl Borrow<str> function taking a tuple of (&str, &str) that somehow
     // could return a single &str (seems impossible).
-    pub fn concatenate(&mut self, left: &LoxString, right: &LoxString) -> LoxString {
+    pub fn concatenate(
+        &mut self,
+        left: &LoxString,
+        right: &LoxString,
+    ) -> Result<LoxString, StringLimitError> {
         // First, build a new Box containing the result of both strings.
         // TODO - would manually making a box of the right size be better? This potentially goes through one realloc.
         // let new_string = Box::new([left.as_str()..right.as_str().len()]);
@@ -280,17 +339,42 @@ impl LoxStringTable {
         self.allocate_string_from_box(new_string)
     }
 
+    // Whether `string` was interned by this table, rather than an equal string
+    // from another one.
+    pub fn owns(&self, string: &LoxString) -> bool {
+        match self.table.get(string.as_str()) {
+            Some(entry) => ptr::eq(entry.as_ref(), string.entry.get()),
+            None => false,
+        }
+    }
+
+    // Drops every string nothing refers to any more. Strings still held
+    // elsewhere, like by a compiled chunk, are kept so they stay valid.
+    pub fn remove_unreferenced(&mut self) {
+        let mut removed_bytes = 0;
+        self.table.retain(|entry| {
+            if *entry.refcount.borrow() == 0 {
+                removed_bytes += entry.data.len();
+                return false;
+            }
+            return true;
+        });
+        self.bytes -= removed_bytes;
+    }
+
     // Remove the interned string from the table, returning true or false if it was removed.
     // The passed in string must be the last owner, and the internal string entry pointer
     // will be removed, so when the LoxString is dropped, there is no dangling pointer.
     //
     // TODO - return Result type vs panic?
+    #[allow(dead_code)]
     pub fn remove_string(&mut self, string: &LoxString) {
         // Take the string, it should exist.
         let entry = self
             .table
             .take(string.as_str())
             .expect("Asked to remove a string not present");
+        self.bytes -= entry.data.len();
 
         // This must be the last owner. Manually clear the last owner, and
         // remove the link in LoxString. Sanity check that the pointer stored
@@ -310,10 +394,10 @@ mod tests {
     fn basic_test() {
         let mut table = LoxStringTable::new();
 
-        let first = table.allocate_string_from_str("abcd");
-        let second = table.allocate_string_from_str("abcd");
-        let third = table.allocate_string_from_str("abcd");
-        let different = table.allocate_string_from_str("abcde");
+        let first = table.allocate_string_from_str("abcd").unwrap();
+        let second = table.allocate_string_from_str("abcd").unwrap();
+        let third = table.allocate_string_from_str("abcd").unwrap();
+        let different = table.allocate_string_from_str("abcde").unwrap();
         assert_eq!(first, second);
         assert_eq!(first, third);
         assert_eq!(second, third);
@@ -337,7 +421,7 @@ mod tests {
         table.table.reserve(100000);
         table.table.shrink_to_fit();
 
-        let fourth = table.allocate_string_from_str("abcd");
+        let fourth = table.allocate_string_from_str("abcd").unwrap();
 
         assert_eq!(first, second);
         assert_eq!(first, third);
@@ -385,18 +469,19 @@ mod tests {
 
         {
             // Allocate the string, then drop it.
-            let unique = table.allocate_string_from_str("abcd");
+            let unique = table.allocate_string_from_str("abcd").unwrap();
 
             assert_eq!(table.table.len(), 1);
 
             table.remove_string(&unique);
             assert_eq!(table.table.len(), 0);
+            assert_eq!(table.total_bytes(), 0);
         }
 
         {
-            let first = table.allocate_string_from_str("abcd");
+            let first = table.allocate_string_from_str("abcd").unwrap();
             {
-                let second = table.allocate_string_from_str("abcd");
+                let second = table.allocate_string_from_str("abcd").unwrap();
                 assert_eq!(table.table.len(), 1);
                 assert_eq!(first, second);
             }
@@ -406,7 +491,7 @@ mod tests {
         assert_eq!(table.table.len(), 0);
 
         // Make a string that should get dropped again.
-        let _first = table.allocate_string_from_str("abcd");
+        let _first = table.allocate_string_from_str("abcd").unwrap();
         assert_eq!(table.table.len(), 1);
     }
 
@@ -419,8 +504,8 @@ mod tests {
             let raw_string: Box<str> = Box::from("abcd");
             let raw_ptr: *const str = raw_string.as_ref();
 
-            let first = table.allocate_string_from_box(raw_string);
-            let second = table.allocate_string_from_str("abcd");
+            let first = table.allocate_string_from_box(raw_string).unwrap();
+            let second = table.allocate_string_from_str("abcd").unwrap();
 
             assert_eq!(raw_ptr, first.string);
             assert_eq!(raw_ptr, second.string);
@@ -447,8 +532,8 @@ mod tests {
             let raw_string: Box<str> = Box::from("asdfasdf");
             let raw_ptr: *const str = raw_string.as_ref();
 
-            let first = table.allocate_string_from_str("asdfasdf");
-            let second = table.allocate_string_from_box(raw_string);
+            let first = table.allocate_string_from_str("asdfasdf").unwrap();
+            let second = table.allocate_string_from_box(raw_string).unwrap();
 
             assert_ne!(raw_ptr, first.string);
             assert_ne!(raw_ptr, second.string);
@@ -476,22 +561,45 @@ mod tests {
         let mut table = LoxStringTable::new();
 
         // test inserting new string
-        let first = table.allocate_string_from_str("abcd");
-        let second = table.allocate_string_from_str("asdf");
+        let first = table.allocate_string_from_str("abcd").unwrap();
+        let second = table.allocate_string_from_str("asdf").unwrap();
 
-        let result = table.concatenate(&first, &second);
+        let result = table.concatenate(&first, &second).unwrap();
         assert_eq!(table.table.len(), 3);
-        let third = table.concatenate(&first, &second);
+        assert_eq!(table.total_bytes(), 16);
+        let third = table.concatenate(&first, &second).unwrap();
         assert_eq!(table.table.len(), 3);
+        assert_eq!(table.total_bytes(), 16);
         assert_eq!(result, third);
 
         // test creating already interned string
-        let first = table.allocate_string_from_str("hello \n");
-        let second = table.allocate_string_from_str("world \n");
+        let first = table.allocate_string_from_str("hello \n").unwrap();
+        let second = table.allocate_string_from_str("world \n").unwrap();
         assert_eq!(table.table.len(), 5);
-        let third = table.allocate_string_from_str("hello \nworld \n");
-        let result = table.concatenate(&first, &second);
+        let third = table.allocate_string_from_str("hello \nworld \n").unwrap();
+        let result = table.concatenate(&first, &second).unwrap();
         assert_eq!(table.table.len(), 6);
         assert_eq!(third, result);
     }
+
+    #[test]
+    fn remove_unreferenced_test() {
+        let mut table = LoxStringTable::new();
+        let mut other = LoxStringTable::new();
+
+        let kept = table.allocate_string_from_str("kept").unwrap();
+        drop(table.allocate_string_from_str("gone").unwrap());
+        assert_eq!(table.total_bytes(), 8);
+
+        table.remove_unreferenced();
+        assert_eq!(table.total_bytes(), 4);
+        assert_eq!(table.table.len(), 1);
+        assert_eq!(kept.as_str(), "kept");
+        assert_eq!(table.allocate_string_from_str("kept").unwrap(), kept);
+
+        // An equal string from another table isn't this one's.
+        let foreign = other.allocate_string_from_str("kept").unwrap();
+        assert!(table.owns(&kept));
+        assert!(!table.owns(&foreign));
+    }
 }
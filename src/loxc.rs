@@ -0,0 +1,301 @@
+use crate::bytecode::OPCODE_VERSION;
+use crate::chunk::Chunk;
+use crate::lox_string_table::LoxStringTable;
+use crate::scanner::SourcePosition;
+use crate::value::Value;
+use std::fmt;
+
+// Saving and loading compiled chunks, so scripts can be shipped precompiled
+// and run without recompiling. A `.loxc` file is laid out as, with all
+// integers little endian:
+//
+//   magic           b"LOXC"
+//   format version  u16, the layout of the file itself
+//   opcode version  u16, `bytecode::OPCODE_VERSION` when it was compiled
+//   constants       u32 count, then for each a u8 tag and its payload:
+//                     0 nil, 1 bool (u8), 2 number (f64 bits),
+//                     3 string (u32 length, then utf-8 bytes)
+//   code            u32 length, then the bytes
+//   positions       u32 count of runs, then for each run of bytes sharing a
+//                   position: u32 run length, i32 line, i32 column,
+//                   u32 start, u32 end
+//
+// Strings are interned into the loading VM's string table, as the VM relies on
+// equal strings being the same object.
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+pub const FORMAT_VERSION: u16 = 1;
+
+const TAG_NIL: u8 = 0;
+const TAG_BOOL: u8 = 1;
+const TAG_NUMBER: u8 = 2;
+const TAG_STRING: u8 = 3;
+
+#[derive(Debug, PartialEq)]
+pub struct LoadError {
+    pub message: String,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+fn error<T>(message: String) -> Result<T, LoadError> {
+    Err(LoadError { message })
+}
+
+/// Whether `bytes` look like a compiled chunk rather than source.
+pub fn is_loxc(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn save(chunk: &Chunk) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&OPCODE_VERSION.to_le_bytes());
+
+    out.extend_from_slice(&(chunk.constants.len() as u32).to_le_bytes());
+    for constant in &chunk.constants {
+        match constant {
+            Value::ValNil => out.push(TAG_NIL),
+            Value::ValBool(value) => {
+                out.push(TAG_BOOL);
+                out.push(*value as u8);
+            }
+            Value::ValNumber(value) => {
+                out.push(TAG_NUMBER);
+                out.extend_from_slice(&value.to_bits().to_le_bytes());
+            }
+            Value::ValObjString(value) => {
+                out.push(TAG_STRING);
+                out.extend_from_slice(&(value.as_str().len() as u32).to_le_bytes());
+                out.extend_from_slice(value.as_str().as_bytes());
+            }
+        }
+    }
+
+    out.extend_from_slice(&(chunk.code.len() as u32).to_le_bytes());
+    out.extend_from_slice(&chunk.code);
+
+    // Operands share their instruction's position, so runs save a lot.
+    let mut runs: Vec<(u32, SourcePosition)> = Vec::new();
+    for position in &chunk.positions {
+        match runs.last_mut() {
+            Some((length, last)) if last == position => *length += 1,
+            _ => runs.push((1, *position)),
+        }
+    }
+
+    out.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+    for (length, position) in runs {
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(&position.line.to_le_bytes());
+        out.extend_from_slice(&position.column.to_le_bytes());
+        out.extend_from_slice(&(position.start as u32).to_le_bytes());
+        out.extend_from_slice(&(position.end as u32).to_le_bytes());
+    }
+
+    out
+}
+
+// Reads fields from a file in order, failing if it's cut short.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], LoadError> {
+        if self.bytes.len() - self.offset < count {
+            return error(format!("File is truncated at byte {}.", self.offset));
+        }
+
+        let taken = &self.bytes[self.offset..self.offset + count];
+        self.offset += count;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, LoadError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+/// Loads a chunk saved by `save`, interning its strings into `string_table`.
+/// Only the file's structure is checked here, the bytecode itself still needs
+/// verifying before it's run.
+pub fn load(bytes: &[u8], string_table: &mut LoxStringTable) -> Result<Chunk, LoadError> {
+    let mut reader = Reader { bytes, offset: 0 };
+
+    if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return error(String::from("Not a compiled lox file."));
+    }
+
+    let format_version = reader.u16()?;
+    if format_version != FORMAT_VERSION {
+        return error(format!(
+            "Unsupported file format version {}, expected {}.",
+            format_version, FORMAT_VERSION
+        ));
+    }
+
+    let opcode_version = reader.u16()?;
+    if opcode_version != OPCODE_VERSION {
+        return error(format!(
+            "Compiled for opcode version {} but this is version {}, recompile it.",
+            opcode_version, OPCODE_VERSION
+        ));
+    }
+
+    let mut chunk = Chunk::new();
+
+    let constant_count = reader.u32()?;
+    for _ in 0..constant_count {
+        let constant = match reader.u8()? {
+            TAG_NIL => Value::ValNil,
+            TAG_BOOL => Value::ValBool(reader.u8()? != 0),
+            TAG_NUMBER => Value::ValNumber(f64::from_bits(reader.u64()?)),
+            TAG_STRING => {
+                let length = reader.u32()? as usize;
+                let offset = reader.offset;
+                match std::str::from_utf8(reader.take(length)?) {
+                    Ok(string) => match string_table.allocate_string_from_str(string) {
+                        Ok(string) => Value::ValObjString(string),
+                        Err(limit) => return error(limit.to_string()),
+                    },
+                    Err(_) => return error(format!("String at byte {} isn't utf-8.", offset)),
+                }
+            }
+            tag => return error(format!("Unknown constant tag {}.", tag)),
+        };
+        chunk.constants.push(constant);
+    }
+
+    let code_length = reader.u32()? as usize;
+    chunk.code = reader.take(code_length)?.to_vec();
+
+    let run_count = reader.u32()?;
+    for _ in 0..run_count {
+        let length = reader.u32()? as usize;
+        let position = SourcePosition {
+            line: reader.u32()? as i32,
+            column: reader.u32()? as i32,
+            start: reader.u32()? as usize,
+            end: reader.u32()? as usize,
+        };
+
+        if length > chunk.code.len() - chunk.positions.len() {
+            return error(String::from("More source positions than bytes of code."));
+        }
+        chunk
+            .positions
+            .extend(std::iter::repeat_n(position, length));
+    }
+
+    if chunk.positions.len() != chunk.code.len() {
+        return error(String::from("Fewer source positions than bytes of code."));
+    }
+
+    if reader.offset != bytes.len() {
+        return error(format!("Unexpected data after byte {}.", reader.offset));
+    }
+
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::load;
+    use super::save;
+    use crate::compiler;
+    use crate::lox_string_table::LoxStringTable;
+    use crate::value::Value;
+
+    fn compile(table: &mut LoxStringTable, source: &str) -> crate::chunk::Chunk {
+        compiler::compile(table, &String::from(source), 1)
+            .ok()
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut compile_table = LoxStringTable::new();
+        let mut chunk = compile(
+            &mut compile_table,
+            "var a = \"héllo\";\nprint a + \"!\";\nprint -1.5 == nil;",
+        );
+        chunk.constants.push(Value::ValBool(true));
+
+        let mut table = LoxStringTable::new();
+        let loaded = load(&save(&chunk), &mut table).unwrap();
+
+        assert_eq!(loaded.code, chunk.code);
+        assert_eq!(loaded.positions, chunk.positions);
+        assert_eq!(
+            loaded.disassemble_to_string("code"),
+            chunk.disassemble_to_string("code")
+        );
+        assert_eq!(loaded.constants.len(), chunk.constants.len());
+        assert_eq!(loaded.constants.last(), Some(&Value::ValBool(true)));
+
+        // Strings end up interned in the table they were loaded into.
+        let a = table.allocate_string_from_str("a").unwrap();
+        assert!(loaded.constants.contains(&Value::ValObjString(a)));
+    }
+
+    fn load_error(bytes: &[u8]) -> String {
+        let mut table = LoxStringTable::new();
+        load(bytes, &mut table).unwrap_err().message
+    }
+
+    #[test]
+    fn load_errors_test() {
+        let mut table = LoxStringTable::new();
+        let bytes = save(&compile(&mut table, "print 1;"));
+
+        assert_eq!(load_error(b"print 1;"), "Not a compiled lox file.");
+        assert_eq!(
+            load_error(&bytes[..bytes.len() - 1]),
+            format!("File is truncated at byte {}.", bytes.len() - 4)
+        );
+
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert_eq!(
+            load_error(&extra),
+            format!("Unexpected data after byte {}.", bytes.len())
+        );
+
+        let mut version = bytes.clone();
+        version[6] = 99;
+        assert_eq!(
+            load_error(&version),
+            "Compiled for opcode version 99 but this is version 1, recompile it."
+        );
+
+        let mut tag = bytes;
+        tag[12] = 9;
+        assert_eq!(load_error(&tag), "Unknown constant tag 9.");
+    }
+}
@@ -1,81 +1,493 @@
+#![allow(clippy::enum_variant_names, clippy::needless_return)]
+
 extern crate num;
 #[macro_use]
 extern crate num_derive;
 
+mod assembler;
 mod bytecode;
 mod chunk;
 mod compiler;
 mod debug;
+mod diagnostic;
+mod fuzz;
 mod lox_string_table;
+mod loxc;
+mod repl;
 mod scanner;
+mod suggest;
+mod test_runner;
 mod value;
+mod verifier;
 mod vm;
 
 use std::env;
 use std::fs;
 use std::io;
-use std::io::Write;
+use std::io::Read;
+use std::path::Path;
 use std::process;
+use std::time::SystemTime;
 
-fn repl() {
-    let mut vm = vm::VM::new();
+#[derive(Clone, Copy, PartialEq)]
+enum ErrorFormat {
+    Human,
+    Json, // One JSON object per line, for tools.
+}
 
-    let mut line = String::new();
+// `source` is None when running a compiled file, which has no source to show.
+fn report_error(
+    error: &vm::InterpretError,
+    source_name: &str,
+    source: Option<&str>,
+    error_format: ErrorFormat,
+) {
+    let errors = match error {
+        vm::InterpretError::InterpretCompileError(diagnostics) => diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.clone(), "compile_error"))
+            .collect(),
+        vm::InterpretError::InterpretRuntimeError(error) => {
+            vec![(error.to_diagnostic(), error.kind.as_str())]
+        }
+    };
 
-    loop {
-        print!("> ");
-        io::stdout().flush().unwrap();
+    let color = diagnostic::stderr_supports_color();
 
-        match io::stdin().read_line(&mut line) {
-            Ok(n) => {
-                if n == 0 {
-                    println!("EOF, exiting...");
-                    process::exit(0);
-                }
+    for (diagnostic, kind) in errors {
+        match (error_format, source) {
+            (ErrorFormat::Human, Some(source)) => {
+                eprintln!("{}", diagnostic.render(source_name, source, color))
+            }
+            (ErrorFormat::Human, None) => eprintln!("{}", diagnostic),
+            (ErrorFormat::Json, _) => eprintln!("{}", diagnostic.to_json(kind, source_name)),
+        }
+    }
+}
+
+fn exit_with_error(
+    error: vm::InterpretError,
+    source_name: &str,
+    source: Option<&str>,
+    error_format: ErrorFormat,
+) -> ! {
+    report_error(&error, source_name, source, error_format);
+
+    let (message, code) = match error {
+        vm::InterpretError::InterpretCompileError(_) => ("Compiler error reading file!", 65),
+        vm::InterpretError::InterpretRuntimeError(_) => ("Runtime error executing file!", 70),
+    };
+
+    if error_format == ErrorFormat::Human {
+        eprintln!("{}", message);
+    }
+    process::exit(code);
+}
+
+// Reports a program that couldn't be loaded or checked, and exits. Human
+// output is `human` as it is, JSON output is an error of `kind` with `message`
+// at `line`, or line 0 when it isn't at any line in particular.
+fn exit_with_program_error(
+    human: String,
+    kind: &str,
+    message: &str,
+    line: i32,
+    source_name: &str,
+    error_format: ErrorFormat,
+) -> ! {
+    match error_format {
+        ErrorFormat::Human => eprintln!("{}", human),
+        ErrorFormat::Json => {
+            let diagnostic = diagnostic::Diagnostic {
+                severity: diagnostic::Severity::Error,
+                message: message.to_string(),
+                line,
+                column: 0,
+                start: 0,
+                end: 0,
+                token: None,
+                note: None,
+            };
+            eprintln!("{}", diagnostic.to_json(kind, source_name));
+        }
+    }
+
+    process::exit(65);
+}
+
+// Where the program comes from.
+enum Input {
+    File(String),
+    Stdin,
+    Code(String), // Given with -e.
+}
 
-                // We throw away the result when doing repl.
-                match vm.interpret(&line) {
-                    _ => {}
-                };
+impl Input {
+    // The name errors are reported against.
+    fn name(&self) -> &str {
+        match self {
+            Input::File(path) => path,
+            Input::Stdin => "<stdin>",
+            Input::Code(_) => "<-e>",
+        }
+    }
+
+    fn read(&self) -> Vec<u8> {
+        let result = match self {
+            Input::File(path) => fs::read(path),
+            Input::Stdin => {
+                let mut bytes = Vec::new();
+                io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+            }
+            Input::Code(code) => Ok(code.clone().into_bytes()),
+        };
 
-                line.clear();
+        match result {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                eprintln!("Couldn't read '{}': {}", self.name(), error);
+                process::exit(66);
             }
-            Err(error) => println!("error: {}", error),
         }
     }
 }
 
-fn run_file(filename: &String) {
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Run,
+    Check,
+    Disasm,
+    Compile,
+    Test,
+    Fuzz,
+}
+
+// Settings shared by every command.
+struct Options {
+    error_format: ErrorFormat,
+    max_errors: usize,
+    debug: debug::DebugOptions,
+}
+
+fn new_vm(options: &mut Options) -> vm::VM {
     let mut vm = vm::VM::new();
+    vm.set_max_compile_errors(options.max_errors);
+    vm.set_debug_options(std::mem::take(&mut options.debug));
+    vm
+}
 
-    let file = fs::read_to_string(filename).expect("Error reading file");
+// Reads the program into a chunk. Files compiled to the `.loxc` format (told
+// apart by their magic header) are loaded, `.loxasm` files are assembled and
+// anything else is compiled. The source is returned too, when there's some to
+// show in errors. Exits if the program can't be read.
+fn load_program(
+    vm: &mut vm::VM,
+    input: &Input,
+    error_format: ErrorFormat,
+) -> (chunk::Chunk, Option<String>) {
+    let name = input.name();
+    let bytes = input.read();
 
-    match vm.interpret(&file) {
-        Ok(()) => {}
-        Err(vm::InterpretError::InterpretCompileError) => {
-            eprintln!("Compiler error reading file!");
-            process::exit(65);
+    if loxc::is_loxc(&bytes) {
+        match vm.load_chunk(&bytes) {
+            Ok(chunk) => return (chunk, None),
+            Err(error) => exit_with_program_error(
+                format!("Couldn't load '{}': {}", name, error),
+                "load_error",
+                &error.message,
+                0,
+                name,
+                error_format,
+            ),
         }
-        Err(vm::InterpretError::InterpretRuntimeError) => {
-            eprintln!("Runtime error executing file!");
-            process::exit(70);
+    }
+
+    let source = match String::from_utf8(bytes) {
+        Ok(source) => source,
+        Err(_) => exit_with_program_error(
+            format!("Couldn't read '{}': it isn't valid UTF-8.", name),
+            "load_error",
+            "The file isn't valid UTF-8.",
+            0,
+            name,
+            error_format,
+        ),
+    };
+
+    // Bytecode written in the disassembler's format.
+    if name.ends_with(".loxasm") {
+        match vm.assemble_chunk(&source) {
+            Ok(chunk) => return (chunk, None),
+            Err(error) => exit_with_program_error(
+                format!("Couldn't assemble '{}': {}", name, error),
+                "assemble_error",
+                &error.message,
+                error.line as i32,
+                name,
+                error_format,
+            ),
         }
     }
+
+    match vm.compile(&source) {
+        Ok(chunk) => (chunk, Some(source)),
+        Err(error) => exit_with_error(error, name, Some(&source), error_format),
+    }
+}
+
+fn run(input: Input, args: Vec<String>, mut options: Options) {
+    let mut vm = new_vm(&mut options);
+    let (chunk, source) = load_program(&mut vm, &input, options.error_format);
+
+    if let Err(error) = vm.set_script_args(input.name(), &args) {
+        eprintln!("{}", error);
+        process::exit(70);
+    }
+
+    if let Err(error) = vm.interpret_chunk(chunk) {
+        exit_with_error(error, input.name(), source.as_deref(), options.error_format);
+    }
+}
+
+fn check(input: Input, mut options: Options) {
+    let mut vm = new_vm(&mut options);
+    let (chunk, _) = load_program(&mut vm, &input, options.error_format);
+
+    // Loaded and assembled bytecode didn't come from the compiler, so it could
+    // still fail verification when run.
+    if let Err(error) = verifier::verify(&chunk) {
+        let line = chunk
+            .positions
+            .get(error.offset)
+            .map_or(0, |position| position.line);
+        exit_with_program_error(
+            format!("Invalid bytecode in '{}': {}", input.name(), error),
+            "invalid_bytecode",
+            &format!("Invalid bytecode: {}", error.message),
+            line,
+            input.name(),
+            options.error_format,
+        );
+    }
+}
+
+fn disasm(input: Input, mut options: Options) {
+    let mut vm = new_vm(&mut options);
+    let (chunk, _) = load_program(&mut vm, &input, options.error_format);
+
+    print!("{}", chunk.disassemble_to_string(input.name()));
+}
+
+// Saves the program in the `.loxc` format. The output defaults to the input's
+// name with a `.loxc` extension.
+fn compile(input: Input, output: Option<String>, mut options: Options) {
+    let output = match (output, &input) {
+        (Some(output), _) => output,
+        (None, Input::File(path)) => Path::new(path)
+            .with_extension("loxc")
+            .to_string_lossy()
+            .into_owned(),
+        (None, _) => usage(),
+    };
+
+    let mut vm = new_vm(&mut options);
+    let (chunk, _) = load_program(&mut vm, &input, options.error_format);
+
+    if let Err(error) = fs::write(&output, loxc::save(&chunk)) {
+        eprintln!("Couldn't write '{}': {}", output, error);
+        process::exit(74);
+    }
+}
+
+// Runs the annotated scripts under a directory, exiting with 1 if any fail.
+fn test(input: Input) {
+    let path = match input {
+        Input::File(path) => path,
+        _ => usage(),
+    };
+
+    match test_runner::run_tests(Path::new(&path)) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(error) => {
+            eprintln!("Couldn't read '{}': {}", path, error);
+            process::exit(66);
+        }
+    }
+}
+
+// Checks random programs, starting from a seed based on the time unless one is
+// given, exiting with 1 if any fail.
+fn fuzz(seed: Option<u64>, count: Option<u64>) {
+    let seed = seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs())
+    });
+
+    if !fuzz::fuzz(seed, count.unwrap_or(1000)) {
+        process::exit(1);
+    }
+}
+
+const USAGE: &str = "\
+Usage: rlox [options]                        Start the REPL.
+       rlox [options] [run] FILE [ARGS...]   Run a script, - reads it from stdin.
+       rlox [options] -e CODE [ARGS...]      Run CODE.
+       rlox [options] check FILE             Compile without running.
+       rlox [options] disasm FILE            Print the bytecode.
+       rlox [options] compile FILE [-o OUT]  Save the bytecode to a .loxc file.
+       rlox test PATH                        Run the annotated .lox scripts under PATH.
+       rlox fuzz [--seed=N] [--count=N]      Check random programs don't crash the VM.";
+
+fn help() {
+    println!("{}", USAGE);
+    println!(
+        "
+FILE can be a script, a .loxc file saved by compile, or .loxasm bytecode in
+the disassembler's format. ARGS are passed to the script as the globals argc
+and arg1 to argN, with arg0 its name. Options go before the script, as
+everything after it is passed through.
+
+The scripts test runs check what they print and the errors they hit against
+'// expect: OUTPUT', '// expect runtime error: MESSAGE' and '// [line N] Error
+...' comments, as in the Crafting Interpreters test suite.
+
+Options:
+  --error-format=human|json  How to print errors, json is one object per line.
+  --max-errors=N             Stop after N compile errors, 0 for no limit
+                             (default {}).
+  --dump-bytecode            Print each chunk's bytecode before running it.
+  --trace                    Print the stack and each instruction as it runs.
+  --debug-output=FILE        Write the two above to FILE instead of stderr.
+                             Release builds need --features debug-output
+                             for these.
+  --seed=N, --count=N        The first program and how many fuzz checks
+                             (default from the time, and 1000).
+  -h, --help                 Print this help.
+  -V, --version              Print the version.",
+        compiler::DEFAULT_MAX_ERRORS
+    );
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    eprintln!("Try 'rlox --help' for more information.");
+    process::exit(64);
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut options = Options {
+        error_format: ErrorFormat::Human,
+        max_errors: compiler::DEFAULT_MAX_ERRORS,
+        debug: debug::DebugOptions::default(),
+    };
+    let mut command = None;
+    let mut input = None;
+    let mut output = None;
+    let mut seed = None;
+    let mut count = None;
+    let mut script_args = Vec::new();
 
-    match args.len() {
-        1 => {
-            repl();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        // Everything after the script being run belongs to it.
+        if input.is_some() && command.unwrap_or(Command::Run) == Command::Run {
+            script_args.push(arg);
+            continue;
         }
-        2 => {
-            run_file(&args[1]);
+
+        match arg.as_str() {
+            "-h" | "--help" => {
+                help();
+                return;
+            }
+            "-V" | "--version" => {
+                println!("rlox {}", env!("CARGO_PKG_VERSION"));
+                return;
+            }
+            "--error-format=human" => options.error_format = ErrorFormat::Human,
+            "--error-format=json" => options.error_format = ErrorFormat::Json,
+            _ if arg.starts_with("--max-errors=") => match arg["--max-errors=".len()..].parse() {
+                Ok(max) => options.max_errors = max,
+                _ => usage(),
+            },
+            "--dump-bytecode" => options.debug.dump_bytecode = true,
+            "--trace" => options.debug.trace_execution = true,
+            _ if arg.starts_with("--debug-output=") => {
+                // Nothing would be written without debug output, so don't
+                // create the file.
+                let path = &arg["--debug-output=".len()..];
+                if debug::DEBUG_OUTPUT_ENABLED {
+                    if let Err(error) = options.debug.output_to_file(path) {
+                        eprintln!("Couldn't open '{}': {}", path, error);
+                        process::exit(74);
+                    }
+                }
+            }
+            _ if arg.starts_with("--seed=") => match arg["--seed=".len()..].parse() {
+                Ok(n) if seed.is_none() => seed = Some(n),
+                _ => usage(),
+            },
+            _ if arg.starts_with("--count=") => match arg["--count=".len()..].parse() {
+                Ok(n) if count.is_none() => count = Some(n),
+                _ => usage(),
+            },
+            "-o" => match args.next() {
+                Some(path) if output.is_none() => output = Some(path),
+                _ => usage(),
+            },
+            "-e" => match args.next() {
+                Some(code) if input.is_none() => input = Some(Input::Code(code)),
+                _ => usage(),
+            },
+            "-" if input.is_none() => input = Some(Input::Stdin),
+            _ if arg.starts_with('-') && arg != "-" => usage(),
+            "run" | "check" | "disasm" | "compile" | "test" | "fuzz"
+                if command.is_none() && input.is_none() =>
+            {
+                command = Some(match arg.as_str() {
+                    "run" => Command::Run,
+                    "check" => Command::Check,
+                    "disasm" => Command::Disasm,
+                    "test" => Command::Test,
+                    "fuzz" => Command::Fuzz,
+                    _ => Command::Compile,
+                });
+            }
+            _ if input.is_none() => input = Some(Input::File(arg)),
+            _ => usage(),
         }
-        _ => {
-            eprintln!("Usage: clox [path]");
-            process::exit(64);
+    }
+
+    if output.is_some() && command != Some(Command::Compile) {
+        usage();
+    }
+
+    if (seed.is_some() || count.is_some()) && command != Some(Command::Fuzz) {
+        usage();
+    }
+
+    if (options.debug.dump_bytecode || options.debug.trace_execution)
+        && !debug::DEBUG_OUTPUT_ENABLED
+    {
+        eprintln!(
+            "Debug output isn't available in release builds without --features debug-output."
+        );
+    }
+
+    match (command, input) {
+        (None, None) => {
+            let vm = new_vm(&mut options);
+            repl::repl(vm, options.error_format)
         }
+        (Some(Command::Fuzz), None) => fuzz(seed, count),
+        (_, None) => usage(),
+        (None, Some(input)) | (Some(Command::Run), Some(input)) => run(input, script_args, options),
+        (Some(Command::Check), Some(input)) => check(input, options),
+        (Some(Command::Disasm), Some(input)) => disasm(input, options),
+        (Some(Command::Compile), Some(input)) => compile(input, output, options),
+        (Some(Command::Test), Some(input)) => test(input),
+        (Some(Command::Fuzz), Some(_)) => usage(),
     }
 }
@@ -0,0 +1,434 @@
+use crate::loxc;
+use crate::report_error;
+use crate::scanner;
+use crate::scanner::Scanner;
+use crate::scanner::TokenType;
+use crate::value::Value;
+use crate::vm::VM;
+use crate::ErrorFormat;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::Context;
+use rustyline::Editor;
+use rustyline::Helper;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Instant;
+
+// The interactive prompt. Lines starting with ':' are commands for looking at
+// and controlling the session rather than code.
+
+// Command names, for completion.
+const COMMANDS: &[&str] = &["disasm", "globals", "help", "load", "reset", "time"];
+
+const HELP: &str = "\
+:globals       List the global variables and their values.
+:disasm CODE   Show the bytecode CODE compiles to, without running it.
+:load FILE     Run FILE in this session, keeping the globals it defines.
+:reset         Forget all globals, as if the repl had just started.
+:time CODE     Run CODE and show how long it took.
+:help          Show this help.";
+
+// Shows a value for inspecting, with strings quoted so they can't be mistaken
+// for other values.
+fn describe(value: &Value) -> String {
+    match value {
+        Value::ValObjString(string) => format!("{:?}", string.as_str()),
+        _ => value.to_string(),
+    }
+}
+
+// Runs a command, without its leading ':'. Output goes to `out`, errors from
+// running code are reported like any other.
+fn command(
+    vm: &mut VM,
+    line: &str,
+    error_format: ErrorFormat,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let line = line.trim();
+    let (name, argument) = match line.find(char::is_whitespace) {
+        Some(end) => (&line[..end], line[end..].trim()),
+        None => (line, ""),
+    };
+
+    match name {
+        "globals" => {
+            let globals = vm.globals();
+            if globals.is_empty() {
+                writeln!(out, "No globals.")?;
+            }
+            for (name, value) in globals {
+                writeln!(out, "{} = {}", name, describe(value))?;
+            }
+        }
+        "disasm" => match vm.compile(&String::from(argument)) {
+            Ok(chunk) => write!(out, "{}", chunk.disassemble_to_string("code"))?,
+            Err(error) => report_error(&error, "repl", Some(argument), error_format),
+        },
+        "load" if !argument.is_empty() => match fs::read(argument) {
+            Ok(bytes) if loxc::is_loxc(&bytes) => match vm.load_chunk(&bytes) {
+                Ok(chunk) => {
+                    if let Err(error) = vm.interpret_chunk(chunk) {
+                        report_error(&error, argument, None, error_format);
+                    }
+                }
+                Err(error) => writeln!(out, "Couldn't load '{}': {}", argument, error)?,
+            },
+            Ok(bytes) => {
+                let source = String::from_utf8_lossy(&bytes).into_owned();
+                if let Err(error) = vm.interpret(&source) {
+                    report_error(&error, argument, Some(&source), error_format);
+                }
+            }
+            Err(error) => writeln!(out, "Couldn't read '{}': {}", argument, error)?,
+        },
+        "reset" => {
+            vm.reset();
+            writeln!(out, "Session reset.")?;
+        }
+        "time" => {
+            let start = Instant::now();
+            let result = vm.interpret(&String::from(argument));
+            let elapsed = start.elapsed();
+
+            if let Err(error) = result {
+                report_error(&error, "repl", Some(argument), error_format);
+            }
+            writeln!(out, "Took {:?}.", elapsed)?;
+        }
+        "help" => writeln!(out, "{}", HELP)?,
+        "load" => writeln!(out, "Usage: :load FILE")?,
+        _ => writeln!(out, "Unknown command ':{}', try :help.", name)?,
+    }
+
+    Ok(())
+}
+
+// Whether more lines are needed to finish the input, because brackets are
+// still open or a string hasn't been closed.
+fn is_incomplete(source: &String) -> bool {
+    let mut scanner = Scanner::new(source);
+    let mut depth = 0;
+
+    loop {
+        let token = scanner.scan_token();
+
+        match token.token_type {
+            TokenType::TokenLeftParen | TokenType::TokenLeftBrace => depth += 1,
+            TokenType::TokenRightParen | TokenType::TokenRightBrace => depth -= 1,
+            TokenType::TokenError if token.string == "Unterminated string." => return true,
+            TokenType::TokenEof => return depth > 0,
+            _ => {}
+        }
+    }
+}
+
+// Runs what was typed. A lone expression without a trailing ';' has its value
+// printed instead of being a missing ';' error.
+fn evaluate(vm: &mut VM, source: &str, error_format: ErrorFormat) {
+    // Without the trailing newline, errors at the end of the input point just
+    // after it rather than at an empty line.
+    let source = &String::from(source.trim_end());
+
+    if source.is_empty() {
+        return;
+    }
+
+    if !source.ends_with(';') {
+        if let Ok(chunk) = vm.compile_expression(source) {
+            if let Err(error) = vm.interpret_chunk(chunk) {
+                report_error(&error, "repl", Some(source), error_format);
+            }
+            return;
+        }
+    }
+
+    if let Err(error) = vm.interpret(source) {
+        report_error(&error, "repl", Some(source), error_format);
+    }
+}
+
+// Tab completion of keywords, globals and repl commands for the line editor.
+struct LoxHelper {
+    globals: Vec<String>, // The session's globals as of the last line run.
+}
+
+// Finds what could complete the word before `pos`, returning where the word
+// starts and the candidates. Right after a leading ':' that's command names,
+// anywhere else keywords and globals.
+fn completions(line: &str, pos: usize, globals: &[String]) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let prefix = &before[start..];
+
+    let mut candidates: Vec<String> = if before[..start].trim_start() == ":" {
+        COMMANDS.iter().map(|command| command.to_string()).collect()
+    } else if prefix.is_empty() {
+        Vec::new()
+    } else {
+        scanner::KEYWORDS
+            .iter()
+            .map(|(keyword, _)| keyword.to_string())
+            .chain(globals.iter().cloned())
+            .collect()
+    };
+
+    candidates.retain(|candidate| candidate.starts_with(prefix));
+    candidates.sort();
+    candidates.dedup();
+
+    (start, candidates)
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(completions(line, pos, &self.globals))
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LoxHelper {}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
+
+// History is kept in the home directory, when there is one.
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".rlox_history"))
+}
+
+pub fn repl(mut vm: VM, error_format: ErrorFormat) {
+    // Ctrl-C stops the statement that's running instead of the whole repl, so
+    // the session's globals survive a runaway script.
+    let interrupt = vm.interrupt_handle();
+    if let Err(error) = ctrlc::set_handler(move || interrupt.interrupt()) {
+        eprintln!("Couldn't install Ctrl-C handler: {}", error);
+    }
+
+    let mut editor: Editor<LoxHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(error) => {
+            eprintln!("Couldn't start the line editor: {}", error);
+            return;
+        }
+    };
+    editor.set_helper(Some(LoxHelper {
+        globals: Vec::new(),
+    }));
+
+    let history = history_path();
+    if let Some(history) = &history {
+        // There's no history the first time.
+        let _ = editor.load_history(history);
+    }
+
+    // What's been typed so far, which may span several lines.
+    let mut source = String::new();
+
+    loop {
+        let prompt = if source.is_empty() { "> " } else { "... " };
+
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C while typing throws away what's been typed.
+            Err(ReadlineError::Interrupted) => {
+                source.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(error) => {
+                eprintln!("error: {}", error);
+                break;
+            }
+        };
+
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+
+        match line.trim_start().strip_prefix(':') {
+            Some(line) if source.is_empty() => {
+                if let Err(error) = command(&mut vm, line, error_format, &mut io::stdout()) {
+                    eprintln!("error: {}", error);
+                }
+            }
+            _ => {
+                // A blank line runs what's there even if it's unfinished, so
+                // there's a way out of a continuation that's never going to
+                // end.
+                let blank = line.trim().is_empty();
+                source.push_str(&line);
+                source.push('\n');
+
+                if !blank && is_incomplete(&source) {
+                    continue;
+                }
+
+                evaluate(&mut vm, &source, error_format);
+                source.clear();
+            }
+        }
+
+        if let Some(helper) = editor.helper_mut() {
+            helper.globals = vm
+                .globals()
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .collect();
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Err(error) = editor.save_history(history) {
+            eprintln!(
+                "Couldn't save history to '{}': {}",
+                history.display(),
+                error
+            );
+        }
+    }
+
+    println!("EOF, exiting...");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::command;
+    use super::completions;
+    use super::is_incomplete;
+    use crate::vm::VM;
+    use crate::ErrorFormat;
+
+    fn run_command(vm: &mut VM, line: &str) -> String {
+        let mut out = Vec::new();
+        command(vm, line, ErrorFormat::Human, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn globals_and_reset_test() {
+        let mut vm = VM::new();
+
+        assert_eq!(run_command(&mut vm, "globals"), "No globals.\n");
+
+        assert!(vm
+            .interpret(&String::from("var b = \"1\"; var a = 1; var c = nil;"))
+            .is_ok());
+        assert_eq!(
+            run_command(&mut vm, "globals"),
+            "a = 1\nb = \"1\"\nc = nil\n"
+        );
+
+        assert_eq!(run_command(&mut vm, " reset "), "Session reset.\n");
+        assert_eq!(run_command(&mut vm, "globals"), "No globals.\n");
+        assert!(vm
+            .interpret(&String::from("var a = \"x\" + \"y\";"))
+            .is_ok());
+        assert_eq!(run_command(&mut vm, "globals"), "a = \"xy\"\n");
+    }
+
+    #[test]
+    fn disasm_and_time_test() {
+        let mut vm = VM::new();
+
+        assert_eq!(
+            run_command(&mut vm, "disasm   var x = 2;"),
+            "== code ==\n\
+             0000    1 OP_CONSTANT      0001 '2'\n\
+             0002    | OP_DEFINE_GLOBAL 0000 'x'\n\
+             0004    | OP_RETURN\n"
+        );
+        // Disassembling doesn't run the code.
+        assert_eq!(run_command(&mut vm, "globals"), "No globals.\n");
+
+        assert!(run_command(&mut vm, "time var x = 2;").starts_with("Took "));
+        assert_eq!(run_command(&mut vm, "globals"), "x = 2\n");
+    }
+
+    #[test]
+    fn unknown_command_test() {
+        let mut vm = VM::new();
+
+        assert_eq!(
+            run_command(&mut vm, "glbals"),
+            "Unknown command ':glbals', try :help.\n"
+        );
+        assert_eq!(run_command(&mut vm, "load"), "Usage: :load FILE\n");
+        assert!(run_command(&mut vm, "load /nonexistent/x.lox").starts_with("Couldn't read"));
+        assert!(run_command(&mut vm, "help").starts_with(":globals"));
+    }
+
+    #[test]
+    fn is_incomplete_test() {
+        let incomplete = |source: &str| is_incomplete(&String::from(source));
+
+        assert!(!incomplete("print 1;\n"));
+        assert!(!incomplete("1 + 2\n"));
+        assert!(incomplete("print (1 +\n"));
+        assert!(incomplete("print ((1) +\n2\n"));
+        assert!(!incomplete("print ((1) +\n2);\n"));
+        assert!(incomplete("{\n"));
+        assert!(incomplete("print \"abc\n"));
+        assert!(!incomplete("print \"abc\ndef\";\n"));
+        assert!(!incomplete("print \"(\";\n"));
+        // Too many closing brackets can't be fixed by reading more.
+        assert!(!incomplete("print 1);\n"));
+    }
+
+    #[test]
+    fn completions_test() {
+        let globals = vec![String::from("value"), String::from("variance")];
+        let complete = |line: &str| completions(line, line.len(), &globals);
+
+        assert_eq!(
+            complete("print va"),
+            (
+                6,
+                vec![
+                    String::from("value"),
+                    String::from("var"),
+                    String::from("variance")
+                ]
+            )
+        );
+        assert_eq!(complete("(1 + pr"), (5, vec![String::from("print")]));
+        assert_eq!(complete("print "), (6, vec![]));
+        assert_eq!(complete("xyz"), (0, vec![]));
+        assert_eq!(complete(":gl"), (1, vec![String::from("globals")]));
+        assert_eq!(
+            complete(" :"),
+            (2, super::COMMANDS.iter().map(|c| c.to_string()).collect())
+        );
+        assert_eq!(complete("é_v"), (0, vec![]));
+
+        // Only the word before the cursor counts.
+        assert_eq!(
+            completions("tr + 1", 2, &globals),
+            (0, vec![String::from("true")])
+        );
+    }
+}
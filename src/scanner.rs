@@ -1,10 +1,15 @@
 // TODO - use non-peeking iterators?
 // TODO - is there any way to not use the string? Shouldn't the scanner just be some iterator/adapter instead?
+#[derive(Clone)]
 pub struct Scanner<'a> {
     source: &'a String,
     start: std::iter::Peekable<std::str::CharIndices<'a>>,
     current: std::iter::Peekable<std::str::CharIndices<'a>>,
     line: i32,
+    line_start: usize,    // Byte offset of the first character on the current line.
+    start_line: i32,      // Line the token currently being scanned starts on.
+    start_column: i32,    // Column of the token currently being scanned.
+    column_offset: usize, // Byte offset start_column was counted up to.
 }
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -59,14 +64,58 @@ pub enum TokenType {
     TokenEof,
 }
 
+// Every keyword, for tools that need the list (like typo suggestions). The
+// scanner itself matches keywords by hand in make_identifier_token.
+pub const KEYWORDS: &[(&str, TokenType)] = &[
+    ("and", TokenType::TokenAnd),
+    ("class", TokenType::TokenClass),
+    ("else", TokenType::TokenElse),
+    ("false", TokenType::TokenFalse),
+    ("for", TokenType::TokenFor),
+    ("fun", TokenType::TokenFun),
+    ("if", TokenType::TokenIf),
+    ("nil", TokenType::TokenNil),
+    ("or", TokenType::TokenOr),
+    ("print", TokenType::TokenPrint),
+    ("return", TokenType::TokenReturn),
+    ("super", TokenType::TokenSuper),
+    ("this", TokenType::TokenThis),
+    ("true", TokenType::TokenTrue),
+    ("var", TokenType::TokenVar),
+    ("while", TokenType::TokenWhile),
+];
+
 // TODO - named struct for every token type seems dumb. How can you just embed these on each type?
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub struct Token<'a> {
     pub string: &'a str, // The slice that actually holds the string containing the token
     pub line: i32,
+    pub column: i32,  // 1-based column of the first character of the token.
+    pub start: usize, // Byte offset of the start of the token in the source.
+    pub end: usize,   // Byte offset one past the end of the token.
     pub token_type: TokenType,
 }
 
+/// Where in the source some piece of code came from.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct SourcePosition {
+    pub line: i32,
+    pub column: i32,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl<'a> Token<'a> {
+    pub fn position(&self) -> SourcePosition {
+        SourcePosition {
+            line: self.line,
+            column: self.column,
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
 trait IsLoxDigit {
     fn is_lox_digit(&self) -> bool;
 }
@@ -109,30 +158,54 @@ impl IsLoxAlpha for Option<char> {
 impl<'a> Scanner<'a> {
     // TODO - self is mut why? cause peek is mut?
     fn make_token(&mut self, token_type: TokenType) -> Token<'a> {
-        match (self.start.peek(), self.current.peek()) {
-            (Some(start), Some(current)) => Token {
-                string: &self.source[start.0..current.0],
-                line: self.line,
-                token_type: token_type,
-            },
-            (Some(start), None) => Token {
-                string: &self.source[start.0..],
-                line: self.line,
-                token_type: token_type,
-            },
-            _ => {
-                // Parser error, should never happen. Start should always be valid.
-                panic!("start was invalid when calling make_token");
-            }
+        if self.start.peek().is_none() {
+            // Parser error, should never happen. Start should always be valid.
+            panic!("start was invalid when calling make_token");
+        }
+
+        let start = self.start_offset();
+        let end = self.current_offset();
+
+        Token {
+            string: &self.source[start..end],
+            line: self.start_line,
+            column: self.start_column,
+            start,
+            end,
+            token_type,
+        }
+    }
+
+    fn start_offset(&mut self) -> usize {
+        match self.start.peek() {
+            Some((offset, _)) => *offset,
+            None => self.source.len(),
+        }
+    }
+
+    fn current_offset(&mut self) -> usize {
+        match self.current.peek() {
+            Some((offset, _)) => *offset,
+            None => self.source.len(),
         }
     }
 
+    // Called after consuming a '\n'.
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current_offset();
+    }
+
     pub fn new(source: &'a std::string::String) -> Self {
         Scanner {
-            source: source,
+            source,
             start: source.char_indices().peekable(),
             current: source.char_indices().peekable(),
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            column_offset: 0,
         }
     }
 
@@ -162,27 +235,19 @@ impl<'a> Scanner<'a> {
     }
 
     fn peek(&mut self) -> Option<char> {
-        match self.current.peek() {
-            Some((_, x)) => Option::Some(*x),
-            None => None,
-        }
+        self.current.peek().map(|(_, x)| *x)
     }
 
     fn peek_next(&self) -> Option<char> {
-        let next = self.current.clone().nth(1);
-
-        match next {
-            Some((_, x)) => Option::Some(x),
-            None => None,
-        }
+        self.current.clone().nth(1).map(|(_, x)| x)
     }
 
     fn skip_whitespace(&mut self) {
         loop {
             match (self.peek(), self.peek_next()) {
                 (Some('\n'), _) => {
-                    self.line += 1;
                     self.advance();
+                    self.new_line();
                 }
                 (Some(x), _) if x.is_whitespace() => {
                     self.advance();
@@ -210,6 +275,17 @@ impl<'a> Scanner<'a> {
 
         self.start = self.current.clone();
 
+        let start = self.start_offset();
+        self.start_line = self.line;
+        // Count on from the previous token rather than from the start of the
+        // line, or scanning one long line is quadratic.
+        if self.column_offset < self.line_start {
+            self.column_offset = self.line_start;
+            self.start_column = 1;
+        }
+        self.start_column += self.source[self.column_offset..start].chars().count() as i32;
+        self.column_offset = start;
+
         // Instead, scan token should return an iterator/option. Basically
         // the scanner should be an iterator that takes a string and output
         // is Iterator<Item = Token>. Then next_token can return an option
@@ -269,16 +345,22 @@ impl<'a> Scanner<'a> {
             Some(_) => return self.make_error_token("Unexpected character."),
             None => Token {
                 string: "",
-                line: self.line,
+                line: self.start_line,
+                column: self.start_column,
+                start: self.source.len(),
+                end: self.source.len(),
                 token_type: TokenType::TokenEof,
             },
         }
     }
 
-    fn make_error_token(&self, string: &'a str) -> Token<'a> {
+    fn make_error_token(&mut self, string: &'a str) -> Token<'a> {
         Token {
-            string: string,
-            line: self.line,
+            string,
+            line: self.start_line,
+            column: self.start_column,
+            start: self.start_offset(),
+            end: self.current_offset(),
             token_type: TokenType::TokenError,
         }
     }
@@ -293,11 +375,11 @@ impl<'a> Scanner<'a> {
                         return self.make_token(TokenType::TokenString);
                     }
 
+                    self.advance();
+
                     if c == '\n' {
-                        self.line += 1;
+                        self.new_line();
                     }
-
-                    self.advance();
                 }
 
                 None => {
@@ -414,10 +496,13 @@ mod tests {
     }
 
     macro_rules! token {
-        ($str:expr, $line:expr, $type:expr) => {
+        ($str:expr, $line:expr, $column:expr, $start:expr, $type:expr) => {
             Token {
                 string: $str,
                 line: $line,
+                column: $column,
+                start: $start,
+                end: $start + $str.len(),
                 token_type: $type,
             }
         };
@@ -488,6 +573,7 @@ mod tests {
         assert_none!(scanner.advance());
     }
 
+    #[allow(dead_code)]
     fn test_check_keyword() {}
 
     // test digit trait
@@ -500,21 +586,109 @@ mod tests {
 
         assert_eq!(
             scanner.scan_token(),
-            token!("print", 1, TokenType::TokenPrint)
+            token!("print", 1, 1, 0, TokenType::TokenPrint)
+        );
+        assert_eq!(
+            scanner.scan_token(),
+            token!("1", 1, 7, 6, TokenType::TokenNumber)
+        );
+        assert_eq!(
+            scanner.scan_token(),
+            token!("+", 1, 9, 8, TokenType::TokenPlus)
+        );
+        assert_eq!(
+            scanner.scan_token(),
+            token!("a", 1, 11, 10, TokenType::TokenIdentifier)
+        );
+        assert_eq!(
+            scanner.scan_token(),
+            token!(";", 1, 12, 11, TokenType::TokenSemicolon)
+        );
+        assert_eq!(
+            scanner.scan_token(),
+            token!("", 1, 13, 12, TokenType::TokenEof)
+        );
+    }
+
+    #[test]
+    fn position_test() {
+        let string = String::from("var a;\n  // comment\n\t\"é\" + b\n\"x\ny\" c");
+        let mut scanner = Scanner::new(&string);
+
+        assert_eq!(
+            scanner.scan_token(),
+            token!("var", 1, 1, 0, TokenType::TokenVar)
+        );
+        scanner.scan_token();
+        scanner.scan_token();
+
+        // Columns count characters, offsets count bytes.
+        assert_eq!(
+            scanner.scan_token(),
+            token!("\"é\"", 3, 2, 21, TokenType::TokenString)
+        );
+        assert_eq!(
+            scanner.scan_token(),
+            token!("+", 3, 6, 26, TokenType::TokenPlus)
+        );
+        assert_eq!(
+            scanner.scan_token(),
+            token!("b", 3, 8, 28, TokenType::TokenIdentifier)
+        );
+
+        // A multi-line string is at the line and column it starts at.
+        assert_eq!(
+            scanner.scan_token(),
+            token!("\"x\ny\"", 4, 1, 30, TokenType::TokenString)
         );
-        assert_eq!(scanner.scan_token(), token!("1", 1, TokenType::TokenNumber));
-        assert_eq!(scanner.scan_token(), token!("+", 1, TokenType::TokenPlus));
         assert_eq!(
             scanner.scan_token(),
-            token!("a", 1, TokenType::TokenIdentifier)
+            token!("c", 5, 4, 36, TokenType::TokenIdentifier)
         );
         assert_eq!(
             scanner.scan_token(),
-            token!(";", 1, TokenType::TokenSemicolon)
+            token!("", 5, 5, 37, TokenType::TokenEof)
+        );
+    }
+
+    #[test]
+    fn error_token_position_test() {
+        let string = String::from("a @ \"abc");
+        let mut scanner = Scanner::new(&string);
+
+        scanner.scan_token();
+
+        let unexpected = scanner.scan_token();
+        assert_eq!(unexpected.token_type, TokenType::TokenError);
+        assert_eq!(
+            (unexpected.column, unexpected.start, unexpected.end),
+            (3, 2, 3)
+        );
+
+        let unterminated = scanner.scan_token();
+        assert_eq!(unterminated.token_type, TokenType::TokenError);
+        assert_eq!(
+            (unterminated.column, unterminated.start, unterminated.end),
+            (5, 4, 8)
         );
-        assert_eq!(scanner.scan_token(), token!("", 1, TokenType::TokenEof));
     }
 
     // test parsing keywords
-    fn keywords_test() {}
+    #[test]
+    fn keywords_test() {
+        for (keyword, token_type) in super::KEYWORDS {
+            let string = keyword.to_string();
+            let mut scanner = Scanner::new(&string);
+            assert_eq!(scanner.scan_token().token_type, *token_type);
+
+            // Anything longer or shorter is just an identifier.
+            let string = format!("{}s", keyword);
+            let mut scanner = Scanner::new(&string);
+            assert_eq!(scanner.scan_token().token_type, TokenType::TokenIdentifier);
+
+            let string = keyword[..keyword.len() - 1].to_string();
+            let mut scanner = Scanner::new(&string);
+            assert_eq!(scanner.scan_token().token_type, TokenType::TokenIdentifier);
+        }
+    }
 }
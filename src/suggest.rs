@@ -0,0 +1,100 @@
+// Helpers for "did you mean ...?" suggestions on typos.
+
+/// The edit distance between two strings, in characters. Insertions,
+/// deletions, substitutions and swapping two adjacent characters (the most
+/// common typo) each count as one edit.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // distances[i][j] is the distance between the first i characters of a and
+    // the first j characters of b.
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+/// Finds the candidate closest to `name`, if any is close enough to plausibly
+/// be what was meant. Ties go to the alphabetically first candidate, so the
+/// result doesn't depend on the order of the candidates.
+pub fn closest<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    // Allow roughly one typo for every three characters.
+    let max_distance = std::cmp::max(1, name.chars().count() / 3);
+    let mut best: Option<(usize, &'a str)> = None;
+
+    for candidate in candidates {
+        if candidate == name {
+            continue;
+        }
+
+        let distance = edit_distance(name, candidate);
+        if distance > max_distance {
+            continue;
+        }
+
+        match best {
+            Some((best_distance, best_candidate))
+                if (best_distance, best_candidate) <= (distance, candidate) => {}
+            _ => best = Some((distance, candidate)),
+        }
+    }
+
+    best.map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::closest;
+    use super::edit_distance;
+
+    #[test]
+    fn edit_distance_test() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("fun", "fun"), 0);
+        assert_eq!(edit_distance("fucn", "fun"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("pritn", "print"), 1);
+        assert_eq!(edit_distance("héllo", "hello"), 1);
+    }
+
+    #[test]
+    fn closest_test() {
+        let names = ["count", "counter", "print", "total"];
+
+        assert_eq!(closest("conut", names.iter().copied()), Some("count"));
+        assert_eq!(closest("countre", names.iter().copied()), Some("counter"));
+        assert_eq!(closest("coun", names.iter().copied()), Some("count"));
+        assert_eq!(closest("coun", names.iter().rev().copied()), Some("count"));
+        assert_eq!(closest("pritn", names.iter().copied()), Some("print"));
+        assert_eq!(closest("xyz", names.iter().copied()), None);
+        assert_eq!(closest("count", names.iter().copied()), None);
+    }
+}
@@ -0,0 +1,282 @@
+// Runs Lox scripts annotated with what they should do, in the format used by
+// the Crafting Interpreters test suite:
+//
+//   print 1 + 2;    // expect: 3
+//   print -"a";     // expect runtime error: Operand must be a number.
+//   print 1 + ;     // Error at ';': Expect expression.
+//   // [line 5] Error at end: Expect expression.
+//
+// `[c line N]` annotations are the C interpreter's, so they apply here, and
+// `[java line N]` ones only apply to the Java interpreter, so they're skipped.
+
+use crate::vm::InterpretError;
+use crate::vm::SharedOutput;
+use crate::vm::VmLimits;
+use crate::vm::VM;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+// Enough for any test script, but stops one that loops forever.
+const MAX_INSTRUCTIONS: u64 = 100_000_000;
+
+#[derive(Debug, Default, PartialEq)]
+struct Expectations {
+    output: Vec<(usize, String)>, // The line of each annotation, and the text.
+    compile_errors: Vec<String>,  // Formatted like `[line 1] Error at 'x': message`.
+    runtime_error: Option<(usize, String)>,
+}
+
+// The rest of `line` after the first `marker` in it. Annotations are found
+// anywhere in a line, as test.py does, so a `//` inside a string before one
+// doesn't hide it.
+fn after<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    return line.find(marker).map(|start| &line[start + marker.len()..]);
+}
+
+fn parse_expectations(source: &str) -> Expectations {
+    let mut expectations = Expectations::default();
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+
+        if let Some(text) = after(line, "// expect:") {
+            let text = text.strip_prefix(' ').unwrap_or(text);
+            expectations.output.push((line_number, text.to_string()));
+        } else if let Some(message) = after(line, "// expect runtime error: ") {
+            expectations.runtime_error = Some((line_number, message.trim().to_string()));
+        } else if let Some(error) = after(line, "// Error") {
+            let error = format!("[line {}] Error{}", line_number, error);
+            expectations.compile_errors.push(error);
+        } else if let Some(rest) = after(line, "// [") {
+            if rest.starts_with("java ") {
+                continue;
+            }
+            let rest = rest.strip_prefix("c ").unwrap_or(rest);
+            if let (true, Some(end)) = (rest.starts_with("line "), rest.find(']')) {
+                let error = rest[end + 1..].trim_start();
+                if error.starts_with("Error") {
+                    let error = format!("[{}] {}", &rest[..end], error);
+                    expectations.compile_errors.push(error);
+                }
+            }
+        }
+    }
+
+    return expectations;
+}
+
+/// Runs a script and checks it against its annotations. Returns a description
+/// of each way it didn't do what they expect, so it passed if there are none.
+pub fn run_script(source: &str) -> Vec<String> {
+    let expected = parse_expectations(source);
+    let output = SharedOutput::default();
+
+    let mut vm = VM::with_limits(VmLimits {
+        max_instructions: Some(MAX_INSTRUCTIONS),
+        ..VmLimits::default()
+    });
+    vm.set_output(Box::new(output.clone()));
+
+    let mut compile_errors = Vec::new();
+    let mut runtime_error = None;
+    match vm.interpret(&source.to_string()) {
+        Ok(()) => {}
+        Err(InterpretError::InterpretCompileError(diagnostics)) => {
+            // The first line, leaving out any note.
+            compile_errors = diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    diagnostic
+                        .to_string()
+                        .lines()
+                        .next()
+                        .unwrap_or("")
+                        .to_string()
+                })
+                .collect();
+        }
+        Err(InterpretError::InterpretRuntimeError(error)) => {
+            runtime_error = Some((error.position.line as usize, error.message));
+        }
+    }
+
+    let mut failures = Vec::new();
+
+    let printed = output.contents();
+    let printed: Vec<&str> = printed.lines().collect();
+    for index in 0..expected.output.len().max(printed.len()) {
+        match (expected.output.get(index), printed.get(index)) {
+            (Some((line, text)), Some(actual)) if text != actual => failures.push(format!(
+                "Expected output '{}' on line {} and got '{}'.",
+                text, line, actual
+            )),
+            (Some((line, text)), None) => failures.push(format!(
+                "Missing expected output '{}' on line {}.",
+                text, line
+            )),
+            (None, Some(actual)) => {
+                failures.push(format!("Got output '{}' when none was expected.", actual))
+            }
+            _ => {}
+        }
+    }
+
+    for error in &expected.compile_errors {
+        if !compile_errors.contains(error) {
+            failures.push(format!("Missing expected error: {}", error));
+        }
+    }
+    for error in &compile_errors {
+        if !expected.compile_errors.contains(error) {
+            failures.push(format!("Unexpected error: {}", error));
+        }
+    }
+
+    match (expected.runtime_error, runtime_error) {
+        (Some((line, message)), Some((actual_line, actual_message))) => {
+            if message != actual_message {
+                failures.push(format!(
+                    "Expected runtime error '{}' and got '{}'.",
+                    message, actual_message
+                ));
+            }
+            if line != actual_line {
+                failures.push(format!(
+                    "Expected runtime error on line {} but was on line {}.",
+                    line, actual_line
+                ));
+            }
+        }
+        (Some((line, message)), None) => failures.push(format!(
+            "Expected runtime error '{}' on line {} and got none.",
+            message, line
+        )),
+        (None, Some((line, message))) => failures.push(format!(
+            "Unexpected runtime error on line {}: {}",
+            line, message
+        )),
+        (None, None) => {}
+    }
+
+    return failures;
+}
+
+// Every `.lox` file under `path`, or `path` itself if it's a file.
+fn collect_scripts(path: &Path, scripts: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        scripts.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_scripts(&path, scripts)?;
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            scripts.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs every `.lox` file under `path`, printing the failures and a summary.
+/// Returns whether they all passed.
+pub fn run_tests(path: &Path) -> io::Result<bool> {
+    let mut scripts = Vec::new();
+    collect_scripts(path, &mut scripts)?;
+    scripts.sort();
+
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for script in &scripts {
+        let failures = match fs::read_to_string(script) {
+            Ok(source) => run_script(&source),
+            Err(error) => vec![format!("Couldn't read it: {}", error)],
+        };
+
+        if failures.is_empty() {
+            passed += 1;
+        } else {
+            failed += 1;
+            println!("FAIL {}", script.display());
+            for failure in failures {
+                println!("     {}", failure);
+            }
+        }
+    }
+
+    println!("{} passed, {} failed.", passed, failed);
+
+    Ok(failed == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_expectations;
+    use super::run_script;
+
+    #[test]
+    fn parse_expectations_test() {
+        let expectations = parse_expectations(
+            "print 1; // expect: 1\n\
+             print \"\"; // expect:\n\
+             print 1 +; // Error at ';': Expect expression.\n\
+             // [line 5] Error at end: Expect expression.\n\
+             // [c line 6] Error: Unexpected character.\n\
+             // [java line 7] Error: Only for Java.\n\
+             -nil; // expect runtime error: Operand must be a number.\n\
+             print \"a // b\"; // expect: a // b\n",
+        );
+
+        assert_eq!(
+            expectations.output,
+            vec![
+                (1, "1".to_string()),
+                (2, "".to_string()),
+                (8, "a // b".to_string())
+            ]
+        );
+        assert_eq!(
+            expectations.compile_errors,
+            vec![
+                "[line 3] Error at ';': Expect expression.",
+                "[line 5] Error at end: Expect expression.",
+                "[line 6] Error: Unexpected character.",
+            ]
+        );
+        assert_eq!(
+            expectations.runtime_error,
+            Some((7, "Operand must be a number.".to_string()))
+        );
+    }
+
+    #[test]
+    fn run_script_test() {
+        assert!(run_script("print 1 + 2; // expect: 3\nprint \"a\"; // expect: a\n").is_empty());
+        assert!(run_script("print 1 +; // Error at ';': Expect expression.\n").is_empty());
+        assert!(run_script(
+            "print 1; // expect: 1\n-nil; // expect runtime error: Operand must be a number.\n"
+        )
+        .is_empty());
+
+        assert_eq!(
+            run_script("print 1; // expect: 2\n// expect: 3\n"),
+            vec![
+                "Expected output '2' on line 1 and got '1'.",
+                "Missing expected output '3' on line 2.",
+            ]
+        );
+        assert_eq!(
+            run_script("print 1 +;\n"),
+            vec!["Unexpected error: [line 1] Error at ';': Expect expression."]
+        );
+        assert_eq!(
+            run_script("// expect runtime error: Operand must be a number.\n-nil;\n"),
+            vec!["Expected runtime error on line 1 but was on line 2."]
+        );
+    }
+}
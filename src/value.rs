@@ -3,6 +3,11 @@ use std::fmt;
 
 // TODO - the book has two different nested types - values and objects.
 // It seems to me they could be flattened? Does it make sense to do that?
+//
+// TODO - enums (`enum Shape { Circle(radius), Rect(w, h), Empty }`) would add
+// two object kinds here: a variant constructor and a tagged instance holding
+// its payload. Constructing needs calls, payload access needs `.` and an
+// exhaustive `match` needs jumps, none of which the compiler has yet.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     ValBool(bool),
@@ -31,3 +36,36 @@ impl Value {
         }
     }
 }
+
+/// A copy of a value that owns its string instead of referencing the VM's
+/// string table, so it can outlive the VM, like the stack kept in a runtime
+/// error.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OwnedValue {
+    ValBool(bool),
+    ValNil,
+    ValNumber(f64),
+    ValString(String),
+}
+
+impl From<&Value> for OwnedValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::ValBool(x) => OwnedValue::ValBool(*x),
+            Value::ValNil => OwnedValue::ValNil,
+            Value::ValNumber(x) => OwnedValue::ValNumber(*x),
+            Value::ValObjString(x) => OwnedValue::ValString(x.as_str().to_string()),
+        }
+    }
+}
+
+impl fmt::Display for OwnedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OwnedValue::ValBool(x) => write!(f, "{}", x),
+            OwnedValue::ValNil => write!(f, "nil"),
+            OwnedValue::ValNumber(x) => write!(f, "{}", x),
+            OwnedValue::ValString(x) => write!(f, "{}", x),
+        }
+    }
+}
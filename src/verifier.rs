@@ -0,0 +1,311 @@
+use crate::bytecode::Opcodes;
+use crate::chunk::Chunk;
+use crate::value::Value;
+use std::fmt;
+
+// Checks that a chunk is safe for the VM to run, so that bad bytecode is
+// reported as an error instead of panicking partway through execution. The
+// compiler's own output is trusted, this is for chunks that came from
+// anywhere else.
+
+#[derive(Debug, PartialEq)]
+pub struct VerifyError {
+    pub offset: usize, // Offset of the offending instruction.
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}: {}", self.offset, self.message)
+    }
+}
+
+// How an instruction's operand is used.
+#[derive(PartialEq)]
+enum Operand {
+    None,
+    Constant,   // Index of any constant.
+    GlobalName, // Index of a string constant naming a global.
+}
+
+fn operand(opcode: Opcodes) -> Operand {
+    match opcode {
+        Opcodes::OpConstant => Operand::Constant,
+        Opcodes::OpGetGlobal | Opcodes::OpDefineGlobal | Opcodes::OpSetGlobal => {
+            Operand::GlobalName
+        }
+        _ => Operand::None,
+    }
+}
+
+// The number of values an instruction needs on the stack, and how many it
+// leaves there after popping those.
+fn stack_effect(opcode: Opcodes) -> (usize, usize) {
+    match opcode {
+        Opcodes::OpReturn => (0, 0),
+        Opcodes::OpPrint => (1, 0),
+        Opcodes::OpConstant => (0, 1),
+        Opcodes::OpNil => (0, 1),
+        Opcodes::OpTrue => (0, 1),
+        Opcodes::OpFalse => (0, 1),
+        Opcodes::OpPop => (1, 0),
+        Opcodes::OpGetGlobal => (0, 1),
+        Opcodes::OpDefineGlobal => (1, 0),
+        Opcodes::OpSetGlobal => (1, 1),
+        Opcodes::OpEqual => (2, 1),
+        Opcodes::OpGreater => (2, 1),
+        Opcodes::OpLess => (2, 1),
+        Opcodes::OpNegate => (1, 1),
+        Opcodes::OpAdd => (2, 1),
+        Opcodes::OpSubtract => (2, 1),
+        Opcodes::OpMultiply => (2, 1),
+        Opcodes::OpDivide => (2, 1),
+        Opcodes::OpNot => (1, 1),
+    }
+}
+
+fn error(offset: usize, message: String) -> Result<(), VerifyError> {
+    Err(VerifyError { offset, message })
+}
+
+pub fn verify(chunk: &Chunk) -> Result<(), VerifyError> {
+    if chunk.positions.len() != chunk.code.len() {
+        return error(
+            0,
+            format!(
+                "Chunk has {} bytes of code but {} source positions.",
+                chunk.code.len(),
+                chunk.positions.len()
+            ),
+        );
+    }
+
+    // First decode every instruction, checking operands and recording where
+    // each instruction starts.
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+
+    while offset < chunk.code.len() {
+        let opcode: Opcodes = match num::FromPrimitive::from_u8(chunk.code[offset]) {
+            Some(opcode) => opcode,
+            None => {
+                return error(offset, format!("Unknown opcode {}.", chunk.code[offset]));
+            }
+        };
+
+        let kind = operand(opcode);
+        if kind != Operand::None {
+            let index = match chunk.code.get(offset + 1) {
+                Some(index) => *index as usize,
+                None => return error(offset, String::from("Missing operand.")),
+            };
+
+            match chunk.constants.get(index) {
+                None => {
+                    return error(
+                        offset,
+                        format!(
+                            "Constant index {} out of range, chunk has {} constants.",
+                            index,
+                            chunk.constants.len()
+                        ),
+                    );
+                }
+                Some(Value::ValObjString(_)) => {}
+                Some(_) if kind == Operand::GlobalName => {
+                    return error(
+                        offset,
+                        format!("Global name constant {} isn't a string.", index),
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+
+        instructions.push((offset, opcode));
+        offset += if kind == Operand::None { 1 } else { 2 };
+    }
+
+    // Then walk every path through the code, checking the stack depth is the
+    // same whichever way an instruction is reached, never underflows, and is
+    // empty when returning. There are no jumps yet, so the only path is
+    // straight through.
+    //
+    // TODO - when jumps are added, check their targets are instruction starts
+    // (a binary search of `instructions`) and push them as successors here.
+    let mut depths: Vec<Option<usize>> = vec![None; instructions.len()];
+    let mut worklist = Vec::new();
+
+    if instructions.is_empty() {
+        return error(0, String::from("Chunk is empty."));
+    }
+    depths[0] = Some(0);
+    worklist.push(0);
+
+    while let Some(index) = worklist.pop() {
+        let (offset, opcode) = instructions[index];
+        let depth = depths[index].unwrap();
+        let (pops, pushes) = stack_effect(opcode);
+
+        if depth < pops {
+            return error(
+                offset,
+                format!(
+                    "Stack underflow, instruction needs {} values but the stack has {}.",
+                    pops, depth
+                ),
+            );
+        }
+
+        let depth = depth - pops + pushes;
+
+        if opcode == Opcodes::OpReturn {
+            if depth != 0 {
+                return error(
+                    offset,
+                    format!("Returning with {} values left on the stack.", depth),
+                );
+            }
+            continue;
+        }
+
+        let next = index + 1;
+        if next >= instructions.len() {
+            return error(
+                offset,
+                String::from("Execution runs off the end of the chunk."),
+            );
+        }
+
+        match depths[next] {
+            None => {
+                depths[next] = Some(depth);
+                worklist.push(next);
+            }
+            Some(existing) if existing != depth => {
+                return error(
+                    instructions[next].0,
+                    format!(
+                        "Stack depth is {} on one path and {} on another.",
+                        existing, depth
+                    ),
+                );
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify;
+    use crate::bytecode::Opcodes;
+    use crate::chunk::Chunk;
+    use crate::lox_string_table::LoxStringTable;
+    use crate::scanner::SourcePosition;
+    use crate::value::Value;
+
+    const POSITION: SourcePosition = SourcePosition {
+        line: 1,
+        column: 1,
+        start: 0,
+        end: 0,
+    };
+
+    fn chunk(code: &[u8], constants: Vec<Value>) -> Chunk {
+        let mut chunk = Chunk::new();
+        for byte in code {
+            chunk.write_chunk(*byte, POSITION);
+        }
+        chunk.constants = constants;
+        chunk
+    }
+
+    macro_rules! op {
+        ($opcode:tt) => {
+            Opcodes::$opcode as u8
+        };
+    }
+
+    fn message(chunk: &Chunk) -> String {
+        verify(chunk).unwrap_err().to_string()
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn valid_chunk_test() {
+        let mut table = LoxStringTable::new();
+        let name = Value::ValObjString(table.allocate_string_from_str("a").unwrap());
+
+        let chunk = chunk(
+            &[
+                op!(OpConstant), 1,
+                op!(OpDefineGlobal), 0,
+                op!(OpGetGlobal), 0,
+                op!(OpNil),
+                op!(OpAdd),
+                op!(OpPrint),
+                op!(OpReturn),
+            ],
+            vec![name, Value::ValNumber(1.0)],
+        );
+
+        assert_eq!(verify(&chunk), Ok(()));
+    }
+
+    #[test]
+    fn decode_errors_test() {
+        assert_eq!(
+            message(&chunk(&[op!(OpNil), 200], vec![])),
+            "0001: Unknown opcode 200."
+        );
+        assert_eq!(
+            message(&chunk(&[op!(OpConstant)], vec![])),
+            "0000: Missing operand."
+        );
+        assert_eq!(
+            message(&chunk(
+                &[op!(OpConstant), 3, op!(OpReturn)],
+                vec![Value::ValNil]
+            )),
+            "0000: Constant index 3 out of range, chunk has 1 constants."
+        );
+        assert_eq!(
+            message(&chunk(
+                &[op!(OpNil), op!(OpSetGlobal), 0],
+                vec![Value::ValNil]
+            )),
+            "0001: Global name constant 0 isn't a string."
+        );
+        assert_eq!(message(&chunk(&[], vec![])), "0000: Chunk is empty.");
+    }
+
+    #[test]
+    fn stack_errors_test() {
+        assert_eq!(
+            message(&chunk(&[op!(OpNil), op!(OpAdd), op!(OpReturn)], vec![])),
+            "0001: Stack underflow, instruction needs 2 values but the stack has 1."
+        );
+        assert_eq!(
+            message(&chunk(&[op!(OpNil), op!(OpReturn)], vec![])),
+            "0001: Returning with 1 values left on the stack."
+        );
+        assert_eq!(
+            message(&chunk(&[op!(OpNil), op!(OpPop)], vec![])),
+            "0001: Execution runs off the end of the chunk."
+        );
+    }
+
+    #[test]
+    fn positions_mismatch_test() {
+        let mut chunk = chunk(&[op!(OpReturn)], vec![]);
+        chunk.positions.clear();
+
+        assert_eq!(
+            message(&chunk),
+            "0000: Chunk has 1 bytes of code but 0 source positions."
+        );
+    }
+}
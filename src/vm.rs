@@ -1,34 +1,244 @@
+use crate::assembler;
 use crate::bytecode::Opcodes;
 use crate::chunk::Chunk;
 use crate::compiler;
-use crate::debug::DEBUG_TRACE_EXECUTION;
+use crate::debug::DebugOptions;
+use crate::debug::DEBUG_OUTPUT_ENABLED;
+use crate::diagnostic::Diagnostic;
+use crate::diagnostic::Severity;
 use crate::lox_string_table::LoxString;
 use crate::lox_string_table::LoxStringTable;
+use crate::lox_string_table::StringLimitError;
+use crate::loxc;
+use crate::scanner;
+use crate::scanner::SourcePosition;
+use crate::suggest;
+use crate::value::OwnedValue;
 use crate::value::Value;
+use crate::verifier;
 use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
 
-// TODO - split compiler and vm runtime errors
 pub enum InterpretError {
-    InterpretCompileError,
-    InterpretRuntimeError,
+    InterpretCompileError(Vec<Diagnostic>),
+    InterpretRuntimeError(RuntimeError),
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RuntimeErrorKind {
+    /// An operand had the wrong type, like negating a string.
+    TypeError,
+    UndefinedVariable,
+    /// The chunk itself was malformed, like an unknown opcode.
+    InvalidBytecode,
+    // Violations of the VM's limits, see VmLimits.
+    StackOverflow,
+    InstructionLimit,
+    StringMemoryLimit,
+    GlobalsLimit,
+    /// Stopped by an InterruptHandle.
+    Interrupted,
+}
+
+impl RuntimeErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuntimeErrorKind::TypeError => "type_error",
+            RuntimeErrorKind::UndefinedVariable => "undefined_variable",
+            RuntimeErrorKind::InvalidBytecode => "invalid_bytecode",
+            RuntimeErrorKind::StackOverflow => "stack_overflow",
+            RuntimeErrorKind::InstructionLimit => "instruction_limit",
+            RuntimeErrorKind::StringMemoryLimit => "string_memory_limit",
+            RuntimeErrorKind::GlobalsLimit => "globals_limit",
+            RuntimeErrorKind::Interrupted => "interrupted",
+        }
+    }
+}
+
+/// An error raised while running a chunk, with enough context to report it
+/// without the VM.
+// The CLI only uses the kind, message and position, the rest is for hosts
+// that log errors.
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub message: String,
+    /// Where the failing instruction came from.
+    pub position: SourcePosition,
+    /// The failing instruction, or `None` if it wasn't a valid opcode.
+    pub opcode: Option<Opcodes>,
+    /// The value stack as it was when the error happened, bottom first. It's
+    /// copied out of the VM's string table, so the error can outlive the VM.
+    pub stack: Vec<OwnedValue>,
+    /// Extra help, like a suggestion for a misspelled variable.
+    pub note: Option<String>,
+}
+
+impl RuntimeError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: self.message.clone(),
+            line: self.position.line,
+            column: self.position.column,
+            start: self.position.start,
+            end: self.position.end,
+            token: None,
+            note: self.note.clone(),
+        }
+    }
+}
+
+/// Caps on the resources a script can use, for running untrusted code. `None`
+/// means unlimited, which is the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VmLimits {
+    /// Maximum number of values on the value stack.
+    pub max_stack_size: Option<usize>,
+    /// Maximum number of instructions executed by a single interpret call.
+    pub max_instructions: Option<u64>,
+    /// Maximum total bytes of all interned strings. This is checked whenever
+    /// a new string is interned: literals while compiling, constants in loaded
+    /// or assembled chunks, script arguments and strings made at runtime.
+    pub max_string_bytes: Option<usize>,
+    /// Maximum number of global variables.
+    pub max_globals: Option<usize>,
+}
+
+// The error for a chunk that can't be run, pointing at the instruction at
+// `offset` where there is one.
+fn invalid_chunk(chunk: &Chunk, offset: usize, message: String) -> InterpretError {
+    let position = chunk
+        .positions
+        .get(offset)
+        .copied()
+        .unwrap_or(SourcePosition {
+            line: 0,
+            column: 0,
+            start: 0,
+            end: 0,
+        });
+
+    return InterpretError::InterpretRuntimeError(RuntimeError {
+        kind: RuntimeErrorKind::InvalidBytecode,
+        message,
+        position,
+        opcode: chunk
+            .code
+            .get(offset)
+            .and_then(|byte| num::FromPrimitive::from_u8(*byte)),
+        stack: Vec::new(),
+        note: None,
+    });
+}
+
+/// Stops a running VM from another thread or a signal handler. The VM checks
+/// for an interrupt before each instruction, and stops with an `Interrupted`
+/// runtime error, keeping its globals.
+#[derive(Clone)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A writer for `set_output` or the debug options that collects what's
+/// written, so it can be read back while the VM owns the writer.
+#[derive(Clone, Default)]
+pub struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+impl SharedOutput {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl io::Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// TODO - coroutines/generators (`yield`, `resume(co, value)`) want each fiber
+// to own its own value stack and call frames. There are no functions or call
+// frames yet (the VM runs a single top level chunk with one ip), so there's
+// nothing to suspend. Revisit once calls land: the stack + ip here should move
+// into a fiber struct, with the VM holding the current one.
 pub struct VM {
     chunk: Chunk,
     ip: usize,
+    instruction_start: usize, // Offset of the instruction being executed.
     stack: Vec<Value>,
     globals: HashMap<LoxString, Value>,
     string_table: LoxStringTable,
+    limits: VmLimits,
+    instructions_executed: u64,
+    interrupted: Arc<AtomicBool>,
+    max_compile_errors: usize,
+    debug: DebugOptions,
+    output: Box<dyn io::Write + Send>, // Where `print` writes.
 }
 
 impl VM {
     pub fn new() -> Self {
+        VM::with_limits(VmLimits::default())
+    }
+
+    pub fn with_limits(limits: VmLimits) -> Self {
+        let mut string_table = LoxStringTable::new();
+        string_table.set_max_bytes(limits.max_string_bytes);
+
         VM {
             chunk: Chunk::new(),
             ip: 0,
+            instruction_start: 0,
             stack: Vec::new(),
             globals: HashMap::new(),
-            string_table: LoxStringTable::new(),
+            string_table,
+            limits,
+            instructions_executed: 0,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            max_compile_errors: compiler::DEFAULT_MAX_ERRORS,
+            debug: DebugOptions::default(),
+            output: Box::new(io::stdout()),
+        }
+    }
+
+    /// Sets where `print` writes, instead of stdout.
+    pub fn set_output(&mut self, output: Box<dyn io::Write + Send>) {
+        self.output = output;
+    }
+
+    /// Sets what debug output to write while compiling and running. This does
+    /// nothing if debug output isn't compiled in.
+    pub fn set_debug_options(&mut self, debug: DebugOptions) {
+        self.debug = debug;
+    }
+
+    /// Sets how many compile errors `interpret` reports before giving up, 0
+    /// for no limit.
+    pub fn set_max_compile_errors(&mut self, max: usize) {
+        self.max_compile_errors = max;
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            interrupted: self.interrupted.clone(),
         }
     }
 
@@ -38,25 +248,188 @@ impl VM {
 
     // C took a format string, but rust you can call format!() instead. This
     // means the C style runtime_error function should be a macro instead?
-    fn runtime_error_formatted(&mut self, message: &str) {
-        eprintln!("{}", message);
-
-        let line = self.chunk.lines[self.ip - 1];
-        eprintln!("[line {}] in script", line);
+    //
+    // Builds the error for the current instruction and resets the stack, so the
+    // VM can keep being used (like in the repl) afterwards.
+    fn runtime_error(&mut self, kind: RuntimeErrorKind, message: &str) -> InterpretError {
+        let error = RuntimeError {
+            kind,
+            message: message.to_string(),
+            position: self.chunk.positions[self.instruction_start],
+            opcode: num::FromPrimitive::from_u8(self.chunk.code[self.instruction_start]),
+            stack: self.stack.iter().map(OwnedValue::from).collect(),
+            note: None,
+        };
 
         self.reset_stack();
+
+        InterpretError::InterpretRuntimeError(error)
+    }
+
+    // Reports an undefined global, suggesting the closest defined global or
+    // keyword in case it was a typo.
+    fn undefined_variable_error(&mut self, name: &LoxString) -> InterpretError {
+        let candidates = self
+            .globals
+            .keys()
+            .map(|global| global.as_str())
+            .chain(scanner::KEYWORDS.iter().map(|(keyword, _)| *keyword));
+        let note = suggest::closest(name.as_str(), candidates)
+            .map(|suggestion| format!("did you mean '{}'?", suggestion));
+
+        let mut error = self.runtime_error(
+            RuntimeErrorKind::UndefinedVariable,
+            format!("Undefined variable '{}'.", name.as_str()).as_str(),
+        );
+
+        if let InterpretError::InterpretRuntimeError(error) = &mut error {
+            error.note = note;
+        }
+
+        error
     }
 
     pub fn interpret(&mut self, source: &String) -> Result<(), InterpretError> {
-        self.chunk = compiler::compile(&mut self.string_table, source)?;
+        // An interrupt only applies to what's running when it's triggered.
+        self.interrupted.store(false, Ordering::Relaxed);
+
+        self.chunk = compiler::compile(&mut self.string_table, source, self.max_compile_errors)?;
         self.ip = 0;
+        self.instructions_executed = 0;
+
+        // The compiler's output should always pass, check that in debug builds.
+        debug_assert_eq!(verifier::verify(&self.chunk), Ok(()));
+
+        self.run_loaded_chunk()
+    }
+
+    /// Compiles a script without running it, ready for `interpret_chunk`.
+    pub fn compile(&mut self, source: &String) -> Result<Chunk, InterpretError> {
+        compiler::compile(&mut self.string_table, source, self.max_compile_errors)
+    }
+
+    /// The global variables and their values, sorted by name.
+    pub fn globals(&self) -> Vec<(&str, &Value)> {
+        let mut globals: Vec<_> = self
+            .globals
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(b.0));
+        globals
+    }
+
+    /// Forgets all globals and strings, as if the VM was new. Limits, debug
+    /// options and interrupt handles are kept, as are the strings of chunks
+    /// from `compile`, `load_chunk` or `assemble_chunk` that are still around,
+    /// so those chunks can still be run.
+    pub fn reset(&mut self) {
+        // The table itself has to stay, as chunks made by this VM point into it.
+        self.globals.clear();
+        self.stack.clear();
+        self.chunk = Chunk::new();
+        self.string_table.remove_unreferenced();
+    }
+
+    /// Compiles a lone expression into a chunk that prints its value, ready
+    /// for `interpret_chunk`.
+    pub fn compile_expression(&mut self, source: &String) -> Result<Chunk, InterpretError> {
+        compiler::compile_expression(&mut self.string_table, source, self.max_compile_errors)
+    }
+
+    /// Gives the script its name and arguments, as the globals `argc` (the
+    /// number of arguments), `arg0` (the name) and `arg1` up to `argN`.
+    // TODO - these should be a single list global once there are lists.
+    pub fn set_script_args(&mut self, name: &str, args: &[String]) -> Result<(), StringLimitError> {
+        let argc = self.string_table.allocate_string_from_str("argc")?;
+        self.globals
+            .insert(argc, Value::ValNumber(args.len() as f64));
+
+        let names = std::iter::once(name).chain(args.iter().map(String::as_str));
+        for (index, arg) in names.enumerate() {
+            let global = self
+                .string_table
+                .allocate_string_from_str(&format!("arg{}", index))?;
+            let value = self.string_table.allocate_string_from_str(arg)?;
+            self.globals.insert(global, Value::ValObjString(value));
+        }
+
+        Ok(())
+    }
 
-        self.run()
+    /// Loads a chunk saved in the `.loxc` format, ready for `interpret_chunk`.
+    pub fn load_chunk(&mut self, bytes: &[u8]) -> Result<Chunk, loxc::LoadError> {
+        loxc::load(bytes, &mut self.string_table)
     }
 
-    fn push(&mut self, value: Value) {
-        // TODO - enforce some stack limit
+    /// Assembles a chunk from text in the disassembler's format, ready for
+    /// `interpret_chunk`.
+    pub fn assemble_chunk(&mut self, text: &str) -> Result<Chunk, assembler::AssembleError> {
+        assembler::assemble(text, &mut self.string_table)
+    }
+
+    /// Runs a chunk that didn't come from compiling source here, like one
+    /// loaded from disk. It's verified first, so bad bytecode is reported as an
+    /// error instead of crashing the VM. The chunk must have been made by this
+    /// VM, as its strings belong to the string table of the VM that made it.
+    pub fn interpret_chunk(&mut self, chunk: Chunk) -> Result<(), InterpretError> {
+        if let Err(error) = verifier::verify(&chunk) {
+            return Err(invalid_chunk(
+                &chunk,
+                error.offset,
+                format!("Invalid bytecode: {}", error.message),
+            ));
+        }
+
+        let foreign = chunk.constants.iter().any(|constant| match constant {
+            Value::ValObjString(string) => !self.string_table.owns(string),
+            _ => false,
+        });
+        if foreign {
+            return Err(invalid_chunk(
+                &chunk,
+                0,
+                String::from("Chunk was made by another VM, its strings aren't this VM's."),
+            ));
+        }
+
+        self.chunk = chunk;
+        self.ip = 0;
+        self.instructions_executed = 0;
+        self.interrupted.store(false, Ordering::Relaxed);
+
+        self.run_loaded_chunk()
+    }
+
+    // Runs the chunk that's just been loaded, writing any debug output asked
+    // for. Debug output is best effort, failing to write it doesn't stop the
+    // script.
+    fn run_loaded_chunk(&mut self) -> Result<(), InterpretError> {
+        if DEBUG_OUTPUT_ENABLED && self.debug.dump_bytecode {
+            let _ = self
+                .chunk
+                .disassemble_to_io(&mut *self.debug.output, "code");
+        }
+
+        let result = self.run();
+
+        let _ = self.output.flush();
+        if DEBUG_OUTPUT_ENABLED {
+            let _ = self.debug.output.flush();
+        }
+
+        result
+    }
+
+    fn push(&mut self, value: Value) -> Result<(), InterpretError> {
+        if let Some(max) = self.limits.max_stack_size {
+            if self.stack.len() >= max {
+                return Err(self.runtime_error(RuntimeErrorKind::StackOverflow, "Stack overflow."));
+            }
+        }
+
         self.stack.push(value);
+        Ok(())
     }
 
     fn pop(&mut self) -> Value {
@@ -93,8 +466,10 @@ impl VM {
                 return Ok(string);
             }
             _ => {
-                self.runtime_error_formatted("OpDefineGlobal constant wasn't a string.");
-                return Err(InterpretError::InterpretRuntimeError);
+                return Err(self.runtime_error(
+                    RuntimeErrorKind::InvalidBytecode,
+                    "OpDefineGlobal constant wasn't a string.",
+                ));
             }
         }
     }
@@ -102,40 +477,60 @@ impl VM {
     fn run(&mut self) -> Result<(), InterpretError> {
         macro_rules! binary_op {
             ($value_type:tt, $op:tt) => {
-                // NOTE - This is different than clox. In clox we peek twice instead
-                // of popping, for GC tracing.
-                //
-                // There's no GC yet, but this rust implementation (shouldn't?) let
-                // things get freed until destructors are called, and since we keep
-                // the variable alive in the match arm, we should be okay.
-                match (self.pop(), self.pop()) {
+                // Peek rather than pop, so the operands are still on the stack
+                // when a type error reports it.
+                match (self.peek(0).clone(), self.peek(1).clone()) {
                     (Value::ValNumber(b), Value::ValNumber(a)) => {
-                        self.push(Value::$value_type(a $op b));
+                        self.pop();
+                        self.pop();
+                        self.push(Value::$value_type(a $op b))?;
                     }
                     _ => {
-                        self.runtime_error_formatted("Operands must be numbers.");
-                        return Err(InterpretError::InterpretRuntimeError);
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::TypeError,
+                            "Operands must be numbers.",
+                        ));
                     }
                 }
             };
         }
 
         loop {
-            if DEBUG_TRACE_EXECUTION {
-                print!("          ");
+            if DEBUG_OUTPUT_ENABLED && self.debug.trace_execution {
+                let out = &mut *self.debug.output;
+
+                let _ = write!(out, "          ");
                 for value in &self.stack {
-                    print!("[{}]", value);
+                    let _ = write!(out, "[{}]", value);
                 }
-                print!("\n");
+                let _ = writeln!(out);
+
+                let _ = self.chunk.disassemble_instruction_to_io(out, self.ip);
+            }
+
+            self.instruction_start = self.ip;
+
+            if self.interrupted.swap(false, Ordering::Relaxed) {
+                return Err(self.runtime_error(RuntimeErrorKind::Interrupted, "Interrupted."));
+            }
 
-                self.chunk.dissasemble_instruction(self.ip);
+            self.instructions_executed += 1;
+            if let Some(max) = self.limits.max_instructions {
+                if self.instructions_executed > max {
+                    return Err(self.runtime_error(
+                        RuntimeErrorKind::InstructionLimit,
+                        format!("Exceeded the limit of {} instructions.", max).as_str(),
+                    ));
+                }
             }
 
             let instruction = num::FromPrimitive::from_u8(self.read_byte());
 
             match instruction {
                 Some(Opcodes::OpPrint) => {
-                    println!("{}", self.pop());
+                    let value = self.pop();
+                    // Like a closed pipe, failing to print doesn't stop the script.
+                    let _ = writeln!(self.output, "{}", value);
                 }
 
                 Some(Opcodes::OpReturn) => {
@@ -144,26 +539,28 @@ impl VM {
 
                 Some(Opcodes::OpConstant) => {
                     let constant = self.read_constant();
-                    self.push(constant);
+                    self.push(constant)?;
                 }
                 Some(Opcodes::OpNegate) => match self.peek(0).clone() {
                     Value::ValNumber(x) => {
                         self.pop();
-                        self.push(Value::ValNumber(-x));
+                        self.push(Value::ValNumber(-x))?;
                     }
                     _ => {
-                        self.runtime_error_formatted("Operand must be a number.");
-                        return Err(InterpretError::InterpretRuntimeError);
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::TypeError,
+                            "Operand must be a number.",
+                        ));
                     }
                 },
                 Some(Opcodes::OpNil) => {
-                    self.push(Value::ValNil);
+                    self.push(Value::ValNil)?;
                 }
                 Some(Opcodes::OpTrue) => {
-                    self.push(Value::ValBool(true));
+                    self.push(Value::ValBool(true))?;
                 }
                 Some(Opcodes::OpFalse) => {
-                    self.push(Value::ValBool(false));
+                    self.push(Value::ValBool(false))?;
                 }
                 Some(Opcodes::OpPop) => {
                     self.pop();
@@ -172,18 +569,25 @@ impl VM {
                     let name = self.read_string()?;
                     let global = self.globals.get(&name);
 
-                    if global == None {
-                        self.runtime_error_formatted(
-                            format!("Undefined variable '{}'.", name.as_str()).as_str(),
-                        );
-                        return Err(InterpretError::InterpretRuntimeError);
+                    if global.is_none() {
+                        return Err(self.undefined_variable_error(&name));
                     }
 
                     let value = global.unwrap().clone();
-                    self.push(value);
+                    self.push(value)?;
                 }
                 Some(Opcodes::OpDefineGlobal) => {
                     let name = self.read_string()?;
+
+                    if let Some(max) = self.limits.max_globals {
+                        if self.globals.len() >= max && !self.globals.contains_key(&name) {
+                            return Err(self.runtime_error(
+                                RuntimeErrorKind::GlobalsLimit,
+                                format!("Exceeded the limit of {} global variables.", max).as_str(),
+                            ));
+                        }
+                    }
+
                     let value = self.peek(0).clone();
                     self.globals.insert(name, value);
                     self.pop();
@@ -192,36 +596,44 @@ impl VM {
                     let name = self.read_string()?;
                     let value = self.peek(0).clone();
 
-                    if self.globals.insert(name.clone(), value) == None {
+                    if self.globals.insert(name.clone(), value).is_none() {
                         self.globals.remove(&name);
-                        self.runtime_error_formatted(
-                            format!("Undefined variable '{}'.", name.as_str()).as_str(),
-                        );
-                        return Err(InterpretError::InterpretRuntimeError);
+                        return Err(self.undefined_variable_error(&name));
                     }
                 }
                 Some(Opcodes::OpEqual) => {
                     let b = self.pop();
                     let a = self.pop();
-                    self.push(Value::ValBool(a == b));
+                    self.push(Value::ValBool(a == b))?;
                 }
                 Some(Opcodes::OpGreater) => binary_op!(ValBool, >),
                 Some(Opcodes::OpLess) => binary_op!(ValBool, <),
                 Some(Opcodes::OpAdd) => match (self.peek(0).clone(), self.peek(1).clone()) {
                     (Value::ValObjString(b), Value::ValObjString(a)) => {
+                        let string = match self.string_table.concatenate(&a, &b) {
+                            Ok(string) => string,
+                            Err(error) => {
+                                return Err(self.runtime_error(
+                                    RuntimeErrorKind::StringMemoryLimit,
+                                    &error.to_string(),
+                                ));
+                            }
+                        };
+
                         self.pop();
                         self.pop();
-                        let string = Value::ValObjString(self.string_table.concatenate(&a, &b));
-                        self.push(string);
+                        self.push(Value::ValObjString(string))?;
                     }
                     (Value::ValNumber(b), Value::ValNumber(a)) => {
                         self.pop();
                         self.pop();
-                        self.push(Value::ValNumber(b + a));
+                        self.push(Value::ValNumber(b + a))?;
                     }
                     _ => {
-                        self.runtime_error_formatted("Operand must be two numbers or two strings.");
-                        return Err(InterpretError::InterpretRuntimeError);
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::TypeError,
+                            "Operand must be two numbers or two strings.",
+                        ));
                     }
                 },
                 Some(Opcodes::OpSubtract) => binary_op!(ValNumber, -),
@@ -229,11 +641,423 @@ impl VM {
                 Some(Opcodes::OpDivide) => binary_op!(ValNumber, /),
                 Some(Opcodes::OpNot) => {
                     let value = Value::ValBool(self.pop().is_falsey());
-                    self.push(value);
+                    self.push(value)?;
                 }
                 // Some(_) => unimplemented!("Opcode not implemented"),
-                None => return Err(InterpretError::InterpretRuntimeError),
+                None => {
+                    return Err(
+                        self.runtime_error(RuntimeErrorKind::InvalidBytecode, "Unknown opcode.")
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InterpretError;
+    use super::InterruptHandle;
+    use super::RuntimeError;
+    use super::RuntimeErrorKind;
+    use super::SharedOutput;
+    use super::VmLimits;
+    use super::VM;
+    use crate::bytecode::Opcodes;
+    use crate::chunk::Chunk;
+    use crate::debug::DebugOptions;
+    use crate::debug::DEBUG_OUTPUT_ENABLED;
+    use crate::loxc;
+    use crate::scanner::SourcePosition;
+    use crate::value::OwnedValue;
+    use crate::value::Value;
+    use std::io;
+
+    fn runtime_error(vm: &mut VM, source: &str) -> RuntimeError {
+        match vm.interpret(&String::from(source)) {
+            Err(InterpretError::InterpretRuntimeError(error)) => error,
+            _ => panic!("expected a runtime error"),
+        }
+    }
+
+    #[test]
+    fn type_error_test() {
+        let output = SharedOutput::default();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(output.clone()));
+        let error = runtime_error(&mut vm, "print 1;\nprint true + (2 * 3);");
+        assert_eq!(output.contents(), "1\n");
+
+        assert_eq!(error.kind, RuntimeErrorKind::TypeError);
+        assert_eq!(error.message, "Operand must be two numbers or two strings.");
+        assert_eq!(error.opcode, Some(Opcodes::OpAdd));
+        assert_eq!((error.position.line, error.position.column), (2, 12));
+        assert_eq!(
+            error.stack,
+            vec![OwnedValue::ValBool(true), OwnedValue::ValNumber(6.0)]
+        );
+
+        // The stack is reset afterwards.
+        assert!(vm.stack.is_empty());
+
+        // Every binary operator keeps its operands for the error.
+        let error = runtime_error(&mut vm, "print 1 - \"a\";");
+        assert_eq!(error.message, "Operands must be numbers.");
+        assert_eq!(error.opcode, Some(Opcodes::OpSubtract));
+        assert_eq!(
+            error.stack,
+            vec![
+                OwnedValue::ValNumber(1.0),
+                OwnedValue::ValString(String::from("a"))
+            ]
+        );
+        assert_eq!(
+            runtime_error(&mut vm, "print nil < 2;").stack,
+            vec![OwnedValue::ValNil, OwnedValue::ValNumber(2.0)]
+        );
+    }
+
+    #[test]
+    fn error_outlives_vm_test() {
+        let error = {
+            let mut vm = VM::new();
+            runtime_error(&mut vm, "var s = \"a\" + \"b\";\n-s;")
+        };
+
+        // The VM and its string table are gone, but the stack isn't.
+        assert_eq!(error.stack, vec![OwnedValue::ValString(String::from("ab"))]);
+        assert_eq!(error.stack[0].to_string(), "ab");
+    }
+
+    #[test]
+    fn undefined_variable_test() {
+        let output = SharedOutput::default();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(output.clone()));
+        let error = runtime_error(&mut vm, "var a = 1;\nb = a;");
+
+        assert_eq!(error.kind, RuntimeErrorKind::UndefinedVariable);
+        assert_eq!(error.message, "Undefined variable 'b'.");
+        assert_eq!(error.opcode, Some(Opcodes::OpSetGlobal));
+        assert_eq!((error.position.line, error.position.column), (2, 1));
+
+        // Globals defined before the error are kept.
+        assert!(vm.interpret(&String::from("print a;")).is_ok());
+        assert_eq!(output.contents(), "1\n");
+    }
+
+    #[test]
+    fn multiple_assignment_test() {
+        let globals = |vm: &VM| -> Vec<String> {
+            vm.globals()
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, value))
+                .collect()
+        };
+
+        let mut vm = VM::new();
+        assert!(vm
+            .interpret(&String::from(
+                "var a = 1;\nvar b = \"two\";\na, b = b, a;\nvar c = -b;"
+            ))
+            .is_ok());
+        assert_eq!(globals(&vm), vec!["a = two", "b = 1", "c = -1"]);
+        assert!(vm.stack.is_empty());
+
+        // An undefined target stops the statement before anything is assigned.
+        let error = runtime_error(&mut vm, "a, d, b = 3, 4, 5;");
+        assert_eq!(error.message, "Undefined variable 'd'.");
+        assert_eq!((error.position.line, error.position.column), (1, 4));
+        assert_eq!(globals(&vm), vec!["a = two", "b = 1", "c = -1"]);
+    }
+
+    #[test]
+    fn undefined_variable_suggestion_test() {
+        let mut vm = VM::new();
+        assert!(vm
+            .interpret(&String::from("var count = 1; var total = 2;"))
+            .is_ok());
+
+        let error = runtime_error(&mut vm, "print conut;");
+        assert_eq!(error.note, Some(String::from("did you mean 'count'?")));
+
+        let error = runtime_error(&mut vm, "totl = 3;");
+        assert_eq!(error.note, Some(String::from("did you mean 'total'?")));
+
+        // Keywords are suggested too.
+        let error = runtime_error(&mut vm, "print nill;");
+        assert_eq!(error.note, Some(String::from("did you mean 'nil'?")));
+
+        let error = runtime_error(&mut vm, "print unrelated;");
+        assert_eq!(error.note, None);
+    }
+
+    #[test]
+    fn limits_test() {
+        let limits = VmLimits {
+            max_stack_size: Some(2),
+            ..VmLimits::default()
+        };
+        let error = runtime_error(&mut VM::with_limits(limits), "print 1 + (2 + 3);");
+        assert_eq!(error.kind, RuntimeErrorKind::StackOverflow);
+        assert_eq!(error.stack.len(), 2);
+
+        let limits = VmLimits {
+            max_instructions: Some(3),
+            ..VmLimits::default()
+        };
+        let output = SharedOutput::default();
+        let mut vm = VM::with_limits(limits);
+        vm.set_output(Box::new(output.clone()));
+        assert!(vm.interpret(&String::from("print 1;")).is_ok());
+        let error = runtime_error(&mut vm, "print 1 + 2;");
+        assert_eq!(error.kind, RuntimeErrorKind::InstructionLimit);
+
+        let limits = VmLimits {
+            max_string_bytes: Some(10),
+            ..VmLimits::default()
+        };
+        let mut vm = VM::with_limits(limits);
+        vm.set_output(Box::new(output.clone()));
+        assert!(vm.interpret(&String::from("print \"ab\" + \"c\";")).is_ok());
+        let error = runtime_error(&mut vm, "print \"abc\" + \"abc\";");
+        assert_eq!(error.kind, RuntimeErrorKind::StringMemoryLimit);
+        assert_eq!(output.contents(), "1\nabc\n");
+
+        let limits = VmLimits {
+            max_globals: Some(1),
+            ..VmLimits::default()
+        };
+        let mut vm = VM::with_limits(limits);
+        assert!(vm.interpret(&String::from("var a = 1; var a = 2;")).is_ok());
+        let error = runtime_error(&mut vm, "var b = 1;");
+        assert_eq!(error.kind, RuntimeErrorKind::GlobalsLimit);
+    }
+
+    #[test]
+    fn string_limit_test() {
+        let limited = || {
+            VM::with_limits(VmLimits {
+                max_string_bytes: Some(8),
+                ..VmLimits::default()
+            })
+        };
+        let limit = "Exceeded the limit of 8 bytes of strings.";
+
+        // Literals are interned by the compiler.
+        let mut vm = limited();
+        match vm.interpret(&String::from("print \"a long string\";")) {
+            Err(InterpretError::InterpretCompileError(diagnostics)) => {
+                assert_eq!(diagnostics[0].message, limit)
+            }
+            _ => panic!("expected a compile error"),
+        }
+        assert!(vm.string_table.total_bytes() <= 8);
+
+        // A string that's already interned doesn't count again, so "abc" fills
+        // the table exactly.
+        let mut vm = limited();
+        assert!(vm
+            .interpret(&String::from("var a = \"abc\";\nvar b = \"ab\" + \"c\";"))
+            .is_ok());
+        assert_eq!(vm.string_table.total_bytes(), 8);
+        let error = runtime_error(&mut vm, "a + b;");
+        assert_eq!(error.kind, RuntimeErrorKind::StringMemoryLimit);
+        assert_eq!(error.message, limit);
+
+        // So are the constants of loaded and assembled chunks.
+        let mut unlimited = VM::new();
+        let chunk = unlimited
+            .compile(&String::from("print \"a long string\";"))
+            .ok()
+            .unwrap();
+        let saved = loxc::save(&chunk);
+        assert_eq!(limited().load_chunk(&saved).unwrap_err().message, limit);
+        assert_eq!(
+            limited()
+                .assemble_chunk(&chunk.disassemble_to_string("code"))
+                .unwrap_err()
+                .message,
+            limit
+        );
+
+        // And script arguments, where "argc" and "arg0" fill the table.
+        let mut vm = limited();
+        assert!(vm.set_script_args("", &[]).is_ok());
+        assert_eq!(
+            vm.set_script_args("", &[String::from("a")])
+                .unwrap_err()
+                .to_string(),
+            limit
+        );
+    }
+
+    // Interrupts the VM from another thread the first time the script prints,
+    // so the interrupt always lands in the middle of the run.
+    struct InterruptOnPrint(Option<InterruptHandle>);
+
+    impl io::Write for InterruptOnPrint {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if let Some(handle) = self.0.take() {
+                std::thread::spawn(move || handle.interrupt())
+                    .join()
+                    .unwrap();
             }
+            Ok(buf.len())
         }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn interrupt_test() {
+        let mut vm = VM::new();
+        assert!(vm.interpret(&String::from("var a = 1;")).is_ok());
+
+        // An interrupt from before the run doesn't count.
+        vm.interrupt_handle().interrupt();
+        vm.set_output(Box::new(InterruptOnPrint(Some(vm.interrupt_handle()))));
+
+        let error = runtime_error(&mut vm, "print a;\na = 2;\na = 3;");
+        assert_eq!(error.kind, RuntimeErrorKind::Interrupted);
+        assert_eq!(error.position.line, 2);
+
+        // The vm is still usable, and keeps what ran before the interrupt.
+        assert!(vm.interpret(&String::from("a = a + 1;")).is_ok());
+        assert_eq!(vm.globals(), vec![("a", &Value::ValNumber(2.0))]);
+    }
+
+    #[test]
+    fn interpret_chunk_verifies_test() {
+        let mut vm = VM::new();
+        let mut chunk = Chunk::new();
+        let position = SourcePosition {
+            line: 3,
+            column: 1,
+            start: 0,
+            end: 0,
+        };
+
+        // A constant that doesn't exist would panic in read_constant.
+        chunk.write_chunk(Opcodes::OpConstant as u8, position);
+        chunk.write_chunk(7, position);
+        chunk.write_chunk(Opcodes::OpPrint as u8, position);
+        chunk.write_chunk(Opcodes::OpReturn as u8, position);
+
+        match vm.interpret_chunk(chunk) {
+            Err(InterpretError::InterpretRuntimeError(error)) => {
+                assert_eq!(error.kind, RuntimeErrorKind::InvalidBytecode);
+                assert_eq!(
+                    error.message,
+                    "Invalid bytecode: Constant index 7 out of range, chunk has 0 constants."
+                );
+                assert_eq!(error.position.line, 3);
+            }
+            _ => panic!("expected a runtime error"),
+        }
+    }
+
+    #[test]
+    fn reset_keeps_chunk_strings_test() {
+        let output = SharedOutput::default();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(output.clone()));
+
+        let chunk = vm
+            .compile(&String::from("var s = \"kept\";\nprint s;"))
+            .ok()
+            .unwrap();
+        assert!(vm.interpret(&String::from("var t = \"gone\";")).is_ok());
+
+        // Strings only the globals used are dropped, the chunk's stay valid.
+        vm.reset();
+        assert!(vm.globals().is_empty());
+        assert_eq!(vm.string_table.total_bytes(), "s".len() + "kept".len());
+
+        assert!(vm.interpret_chunk(chunk).is_ok());
+        assert_eq!(output.contents(), "kept\n");
+    }
+
+    #[test]
+    fn interpret_chunk_foreign_test() {
+        let mut other = VM::new();
+        let mut vm = VM::new();
+
+        let chunk = other.compile(&String::from("print \"a\";")).ok().unwrap();
+        match vm.interpret_chunk(chunk) {
+            Err(InterpretError::InterpretRuntimeError(error)) => {
+                assert_eq!(error.kind, RuntimeErrorKind::InvalidBytecode);
+                assert_eq!(
+                    error.message,
+                    "Chunk was made by another VM, its strings aren't this VM's."
+                );
+            }
+            _ => panic!("expected a runtime error"),
+        }
+    }
+
+    #[test]
+    fn script_args_test() {
+        let mut vm = VM::new();
+        assert!(vm
+            .set_script_args("test.lox", &[String::from("a"), String::from("b")])
+            .is_ok());
+
+        assert!(vm
+            .interpret(&String::from(
+                "var all = arg0 + \" \" + arg1 + \" \" + arg2;"
+            ))
+            .is_ok());
+        assert_eq!(
+            runtime_error(&mut vm, "-all;").stack,
+            vec![OwnedValue::ValString(String::from("test.lox a b"))]
+        );
+        assert_eq!(
+            runtime_error(&mut vm, "-(argc == 2);").stack,
+            vec![OwnedValue::ValBool(true)]
+        );
+    }
+
+    #[test]
+    fn debug_options_test() {
+        let source = String::from("print 1;");
+        let buffer = SharedOutput::default();
+        let output = SharedOutput::default();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(output.clone()));
+
+        vm.set_debug_options(DebugOptions {
+            output: Box::new(buffer.clone()),
+            ..DebugOptions::default()
+        });
+        assert!(vm.interpret(&source).is_ok());
+        assert_eq!(buffer.contents(), "");
+
+        vm.set_debug_options(DebugOptions {
+            dump_bytecode: true,
+            trace_execution: true,
+            output: Box::new(buffer.clone()),
+        });
+        assert!(vm.interpret(&source).is_ok());
+
+        if DEBUG_OUTPUT_ENABLED {
+            assert_eq!(
+                buffer.contents(),
+                "== code ==\n\
+                 0000    1 OP_CONSTANT      0000 '1'\n\
+                 0002    | OP_PRINT\n\
+                 0003    | OP_RETURN\n          \n\
+                 0000    1 OP_CONSTANT      0000 '1'\n          [1]\n\
+                 0002    | OP_PRINT\n          \n\
+                 0003    | OP_RETURN\n"
+            );
+        } else {
+            assert_eq!(buffer.contents(), "");
+        }
+
+        // The script's own output isn't mixed in with the debug output.
+        assert_eq!(output.contents(), "1\n1\n");
     }
 }